@@ -49,6 +49,26 @@ fn parse_fail(c: &mut Criterion) {
     });
 }
 
+fn parse_json(c: &mut Criterion) {
+    let mut source = String::from("[");
+    for i in 0..100 {
+        if i > 0 {
+            source.push_str(",\n");
+        }
+        source.push_str(&format!(
+            r#"{{"id": {}, "name": "item \u00e9{}", "tags": ["a", "b"], "price": {}.5, "stock": null}}"#,
+            i, i, i
+        ));
+    }
+    source.push(']');
+
+    c.bench_function("Parse JSON", |b| {
+        b.iter(|| {
+            black_box(grammars::json::parse(&source).unwrap());
+        })
+    });
+}
+
 fn vm_addition(c: &mut Criterion) {
     let mut instructions = vec![Instruction::Push(Values::Int(0))];
     for i in 0..1000 {
@@ -68,6 +88,7 @@ fn vm_addition(c: &mut Criterion) {
     });
 }
 
+#[allow(clippy::vec_init_then_push)]
 fn vm_loop(c: &mut Criterion) {
     let mut instructions = vec![];
     instructions.push(Instruction::Push(Values::Int(0)));
@@ -93,5 +114,5 @@ fn vm_loop(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, parse_success, parse_fail, vm_addition, vm_loop);
+criterion_group!(benches, parse_success, parse_fail, parse_json, vm_addition, vm_loop);
 criterion_main!(benches);
@@ -1,10 +1,12 @@
 
 
-#[derive(Debug, Clone, PartialEq, Hash, Eq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Int(i32),
+    Float(f64),
     Str(String),
     Bool(bool),
+    Null,
     Symbol(String),
     Ident(String, Box<Expr>),
     Call(String, Vec<Expr>),
@@ -14,16 +16,38 @@ pub enum Expr {
     Multiply(Box<Expr>, Box<Expr>),
     Divide(Box<Expr>, Box<Expr>),
     Modulus(Box<Expr>, Box<Expr>),
-    If(Box<Expr>, Vec<Expr>, Vec<Expr>),
+    /// Without an `else`, an `if` whose condition is false yields null.
+    If(Box<Expr>, Vec<Expr>, Option<Vec<Expr>>),
     Equals(Box<Expr>, Box<Expr>),
+    NotEquals(Box<Expr>, Box<Expr>),
     LessThan(Box<Expr>, Box<Expr>),
+    LessThanOrEqual(Box<Expr>, Box<Expr>),
     GreaterThan(Box<Expr>, Box<Expr>),
+    GreaterThanOrEqual(Box<Expr>, Box<Expr>),
     While(Box<Expr>, Vec<Expr>),
     And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Array(Vec<Expr>),
+    /// `{key: value, ...}`, with entries in source order.
+    Map(Vec<(Expr, Expr)>),
+    /// `array[index]`
+    Index(Box<Expr>, Box<Expr>),
+    /// `array[index] = value`
+    SetIndex(Box<Expr>, Box<Expr>, Box<Expr>),
+    /// `for name in array { body }`
+    For(String, Box<Expr>, Vec<Expr>),
+    /// `fn(params) { body }`
+    Lambda(Vec<String>, Vec<Expr>),
+    /// Calls the function that an expression yields, as in `f(x)(y)`.
+    Apply(Box<Expr>, Vec<Expr>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Function {
+    /// Text of the `///` comments before the function, one line each.
+    pub doc: Option<String>,
     pub name: String,
     pub params : Vec<String>,
     pub body: Vec<Expr>,
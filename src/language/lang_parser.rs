@@ -1,24 +1,106 @@
 use std::collections::HashMap;
-
+use std::rc::Rc;
 
 
 use crate::ast::*;
+use crate::parser_combinator::indent::continues_line;
+use crate::parser_combinator::numeric::{bin, fractional, hex, integer, oct};
 use crate::parser_combinator::parser::*;
+use crate::parser_combinator::string::{raw_string_literal, string_literal};
+use crate::parser_combinator::{Expected, Grammar, ParseError, ParseSession, Syntax};
+
+// A `///` line that does not document a function, which is read as an
+// ordinary comment. One that does is left for `doc_comment`.
+struct StrayDocComment<'a> {
+    line: RcParser<'a, &'a str>,
+    // Whitespace and line comments up to the start of a named function.
+    documents: RcParser<'a, &'a str>,
+}
+
+impl<'a> Parser<'a> for StrayDocComment<'a> {
+    type Output = ();
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let (_, remaining) = self.line.parse_in(input, session)?;
+        match self.documents.parse_in(remaining, session) {
+            Ok(_) => Err(ParseError::new("Doc comment documents a function", input)),
+            Err(_) => Ok(((), remaining)),
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.line.describe(grammar)
+    }
+}
+
+// Nestable `/* */` comments and `//` line comments. Lines starting with
+// exactly three slashes are doc comments when a named function follows, and
+// ordinary comments anywhere else.
+fn comment<'a>() -> RcParser<'a, ()> {
+    let line = pattern(r"//([^/\n][^\n]*|//[^\n]*|\n|$)").map(|_| ());
+    let stray_doc = StrayDocComment {
+        line: pattern(r"///([^/\n][^\n]*)?(\n|$)"),
+        documents: pattern(r"([ \t\n\r]+|//[^\n]*\n)*fn([ \t\n\r]+|/\*[^*]*\*+/)*[a-zA-Z]"),
+    }
+    .to_rc();
+
+    let mut block = forward();
+    let nested: RcParser<'a, ()> = block.clone();
+    let text = choice(vec![
+        nested,
+        pattern(r"[^*/]+").map(|_| ()),
+        pattern(r"\*+[^*/]").map(|_| ()),
+        pchar('/').map(|_| ()),
+    ]);
+    let block_comment = text
+        .many()
+        .between(pstring("/*"), pattern(r"\*+/"))
+        .map(|_| ());
+    set_implementation(&mut block, block_comment);
+
+    let block: RcParser<'a, ()> = block;
+    choice(vec![block, line, stray_doc])
+        .token("comment")
+        .named("comment")
+}
+
+// Whitespace and comments, which are allowed anywhere whitespace is.
+fn trivia<'a>() -> RcParser<'a, ()> {
+    let space = pattern(r"[ \t\n\r]+").token("whitespace").map(|_| ());
+    space.or(comment()).many().map(|_| ()).named("trivia")
+}
+
+// Like `trivia`, but requires at least some, such as after a keyword.
+fn trivia1<'a>() -> RcParser<'a, ()> {
+    let space = pattern(r"[ \t\n\r]+").token("whitespace").map(|_| ());
+    space.or(comment()).many1().map(|_| ()).named("trivia1")
+}
+
+fn doc_comment<'a>() -> RcParser<'a, Option<String>> {
+    let line = pattern(r"///([^/\n][^\n]*)?\n")
+        .token("doc_comment")
+        .left(trivia())
+        .map(|line: &str| {
+            let text = line[3..].trim_end_matches(['\n', '\r']);
+            text.strip_prefix(' ').unwrap_or(text).to_string()
+        });
+    line.many()
+        .map(|lines| (!lines.is_empty()).then(|| lines.join("\n")))
+}
+
+fn float<'a>() -> RcParser<'a, Expr> {
+    fractional().map(Expr::Float).token("float").named("float")
+}
 
 fn int<'a>() -> RcParser<'a, Expr> {
-    let any_number = any_of(&['0', '1', '2', '3', '4', '5', '6', '7', '8', '9']);
-    let number_parser = pchar('-').optional().then(any_number.many1());
-
-    number_parser
-        .map(move |(negate, value): (Option<char>, Vec<char>)| {
-            let string: String = value.into_iter().collect();
-            let number = string.parse::<i32>().expect("Incorrectly parsed sequence as an Integer");
-            match negate {
-                Some(_) => -number,
-                None => number,
-            }
-        })
+    // Prefixed forms first, or `integer` would stop at their leading `0`.
+    choice(vec![hex::<i32>(), oct::<i32>(), bin::<i32>(), integer::<i32>()])
         .map(Expr::Int)
+        .token("int")
+        .named("int")
 }
 
 fn string_ident<'a>() -> RcParser<'a, String> {
@@ -30,21 +112,17 @@ fn string_ident<'a>() -> RcParser<'a, String> {
 
     chars
         .map(move |value: Vec<char>| value.into_iter().collect())
-        .ws()
+        .token("ident")
+        .left(trivia())
+        .named("identifier")
 }
 
 fn quoted_string<'a>() -> RcParser<'a, Expr> {
-    let mut allowed_chars = Vec::new();
-    for c in "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ `¬!123£4$5%6^7&8*9(0)-_=+|,<.>/?".chars() {
-        allowed_chars.push(c);
-    }
-    let chars = any_of(&allowed_chars).many1();
-
-    chars
-        .map(move |value: Vec<char>| value.into_iter().collect())
-        .ws()
-        .between(pchar('\"'), pchar('\"'))
+    raw_string_literal()
+        .or(string_literal())
         .map(Expr::Str)
+        .token("string")
+        .named("string")
 }
 
 fn string_symbol<'a>() -> RcParser<'a, Expr> {
@@ -57,164 +135,386 @@ fn string_symbol<'a>() -> RcParser<'a, Expr> {
     chars
         .map(move |value: Vec<char>| value.into_iter().collect())
         .map(Expr::Symbol)
+        .token("ident")
+        .named("symbol")
 }
 
 fn bool<'a>() -> RcParser<'a, Expr> {
-    let true_ = pstring("true");
-    let false_ = pstring("false");
-    true_.or(false_).map(|s| Expr::Bool(s == "true"))
+    let true_ = keyword("true");
+    let false_ = keyword("false");
+    true_
+        .or(false_)
+        .map(|s| Expr::Bool(s == "true"))
+        .token("bool")
+        .named("bool")
 }
 
-fn math(symbol: char, expr: RcParser<Expr>) -> RcParser<(Expr, Expr)> {
-    let lparen = pchar('(').ws();
-    let rparen = pchar(')').ws();
-    let operator = pchar(symbol).ws();
-
-    lparen
-        .right(expr.clone())
-        .left(operator)
-        .then(expr.clone())
-        .left(rparen)
+// `word` on its own, not the start of a longer name such as `nullable`.
+struct KeywordParser<'a> {
+    word: &'static str,
+    parser: RcParser<'a, &'static str>,
 }
 
-fn add(expr: RcParser<Expr>) -> RcParser<Expr> {
-    math('+', expr).map(|(lhs, rhs)| Expr::Add(Box::new(lhs), Box::new(rhs)))
-}
+impl<'a> Parser<'a> for KeywordParser<'a> {
+    type Output = &'static str;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let (word, remaining) = self.parser.parse_in(input, session)?;
+        if remaining.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return Err(ParseError::new(format!("Expected {:?}", self.word), input)
+                .with_expected(Expected::String(self.word)));
+        }
+        Ok((word, remaining))
+    }
 
-fn subtract(expr: RcParser<Expr>) -> RcParser<Expr> {
-    math('-', expr).map(|(lhs, rhs)| Expr::Subtract(Box::new(lhs), Box::new(rhs)))
-}
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
 
-fn multiply(expr: RcParser<Expr>) -> RcParser<Expr> {
-    math('*', expr).map(|(lhs, rhs)| Expr::Multiply(Box::new(lhs), Box::new(rhs)))
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.parser.describe(grammar)
+    }
 }
 
-fn divide(expr: RcParser<Expr>) -> RcParser<Expr> {
-    math('/', expr).map(|(lhs, rhs)| Expr::Divide(Box::new(lhs), Box::new(rhs)))
+fn keyword<'a>(word: &'static str) -> RcParser<'a, &'static str> {
+    KeywordParser {
+        word,
+        parser: pstring(word),
+    }
+    .to_rc()
 }
 
-fn modulus(expr: RcParser<Expr>) -> RcParser<Expr> {
-    math('%', expr).map(|(lhs, rhs)| Expr::Modulus(Box::new(lhs), Box::new(rhs)))
+fn null<'a>() -> RcParser<'a, Expr> {
+    keyword("null").map(|_| Expr::Null).token("null").named("null")
 }
 
-fn comparison<'a>(symbol: &'static str, expr: RcParser<'a, Expr>) -> RcParser<'a, (Expr, Expr)> {
-    let comparison = pstring(symbol).ws();
-    let lparen = pchar('(').ws();
-    let rparen = pchar(')').ws();
+type BinaryOp = fn(Expr, Expr) -> Expr;
 
-    lparen
-        .right(expr.clone())
-        .left(comparison)
-        .then(expr.clone())
-        .left(rparen)
+// An operator has to be on the same line as its left operand; otherwise the
+// `-` of `a\n-1` would join two expressions.
+fn operator<'a>(symbol: &'static str, op: BinaryOp) -> RcParser<'a, BinaryOp> {
+    continues_line(pstring(symbol))
+        .token("operator")
+        .left(trivia())
+        .map(move |_| op)
 }
 
-fn equals(expr: RcParser<Expr>) -> RcParser<Expr> {
-    comparison("==", expr).map(|(lhs, rhs)| Expr::Equals(Box::new(lhs), Box::new(rhs)))
+// One precedence level: operands separated by any of `operators`, which
+// associate to the left.
+fn binary<'a>(
+    operand: RcParser<'a, Expr>,
+    operators: Vec<RcParser<'a, BinaryOp>>,
+    name: &'static str,
+) -> RcParser<'a, Expr> {
+    operand.chainl1(choice(operators)).named(name)
 }
 
-fn lt(expr: RcParser<Expr>) -> RcParser<Expr> {
-    comparison("<", expr).map(|(lhs, rhs)| Expr::LessThan(Box::new(lhs), Box::new(rhs)))
+type UnaryOp = fn(Expr) -> Expr;
+
+fn prefix<'a>(symbol: char, operand: RcParser<'a, Expr>, op: UnaryOp) -> RcParser<'a, Expr> {
+    pchar(symbol)
+        .token("operator")
+        .left(trivia())
+        .right(operand)
+        .map(op)
 }
 
-fn gt(expr: RcParser<Expr>) -> RcParser<Expr> {
-    comparison(">", expr).map(|(lhs, rhs)| Expr::GreaterThan(Box::new(lhs), Box::new(rhs)))
+// Negative literals such as `-1` are read by `primary` before `-` is tried
+// as an operator.
+fn unary<'a>(primary: RcParser<'a, Expr>) -> RcParser<'a, Expr> {
+    let mut unary = forward();
+    let operand: RcParser<'a, Expr> = unary.clone();
+    let implementation = choice(vec![
+        primary,
+        prefix('!', operand.clone(), |e| Expr::Not(Box::new(e))),
+        prefix('-', operand, |e| Expr::Negate(Box::new(e))),
+    ])
+    .named("unary");
+    set_implementation(&mut unary, implementation);
+    unary
 }
 
-fn and(expr: RcParser<Expr>) -> RcParser<Expr> {
-    comparison("&&", expr).map(|(lhs, rhs)| Expr::And(Box::new(lhs), Box::new(rhs)))
+fn infix<'a>(primary: RcParser<'a, Expr>) -> RcParser<'a, Expr> {
+    let product = binary(
+        unary(primary),
+        vec![
+            operator("*", |l, r| Expr::Multiply(Box::new(l), Box::new(r))),
+            operator("/", |l, r| Expr::Divide(Box::new(l), Box::new(r))),
+            operator("%", |l, r| Expr::Modulus(Box::new(l), Box::new(r))),
+        ],
+        "product",
+    );
+    let sum = binary(
+        product,
+        vec![
+            operator("+", |l, r| Expr::Add(Box::new(l), Box::new(r))),
+            operator("-", |l, r| Expr::Subtract(Box::new(l), Box::new(r))),
+        ],
+        "sum",
+    );
+    let comparison = binary(
+        sum,
+        vec![
+            // Longest first, or `<` would match the start of `<=`.
+            operator("<=", |l, r| Expr::LessThanOrEqual(Box::new(l), Box::new(r))),
+            operator(">=", |l, r| Expr::GreaterThanOrEqual(Box::new(l), Box::new(r))),
+            operator("<", |l, r| Expr::LessThan(Box::new(l), Box::new(r))),
+            operator(">", |l, r| Expr::GreaterThan(Box::new(l), Box::new(r))),
+        ],
+        "comparison",
+    );
+    let equality = binary(
+        comparison,
+        vec![
+            operator("==", |l, r| Expr::Equals(Box::new(l), Box::new(r))),
+            operator("!=", |l, r| Expr::NotEquals(Box::new(l), Box::new(r))),
+        ],
+        "equality",
+    );
+    let and = binary(
+        equality,
+        vec![operator("&&", |l, r| Expr::And(Box::new(l), Box::new(r)))],
+        "and",
+    );
+    binary(
+        and,
+        vec![operator("||", |l, r| Expr::Or(Box::new(l), Box::new(r)))],
+        "or",
+    )
 }
 
 fn while_loop<'a>(expr: RcParser<'a, Expr>, body: RcParser<'a, Vec<Expr>>) -> RcParser<'a, Expr> {
-    let while_ = pstring("while").ws1();
+    let while_ = pstring("while").token("keyword").left(trivia1());
     let cond = expr.clone();
 
     while_
         .right(cond)
         .then(body.clone())
         .map(|(cond, body)| Expr::While(Box::new(cond), body))
+        .node("while")
+        .named("while")
+}
+
+fn for_loop<'a>(expr: RcParser<'a, Expr>, body: RcParser<'a, Vec<Expr>>) -> RcParser<'a, Expr> {
+    let for_ = pstring("for").token("keyword").left(trivia1());
+    let in_ = pstring("in").token("keyword").left(trivia1());
+
+    for_.right(string_ident())
+        .left(in_)
+        .then(expr)
+        .then(body)
+        .map(|((name, array), body)| Expr::For(name, Box::new(array), body))
+        .node("for")
+        .named("for")
+}
+
+fn array(expr: RcParser<Expr>) -> RcParser<Expr> {
+    expr.sep_by(pchar(',').left(trivia()))
+        .between(pchar('[').left(trivia()), pchar(']'))
+        .map(Expr::Array)
+        .node("array")
+        .named("array")
+}
+
+// `{` also opens a body, but a map literal only appears where a value is
+// expected, and a body never does.
+fn map(expr: RcParser<Expr>) -> RcParser<Expr> {
+    let entry = expr
+        .clone()
+        .left(pchar(':').left(trivia()))
+        .then(expr)
+        .node("entry")
+        .named("entry");
+    entry
+        .sep_by(pchar(',').left(trivia()))
+        .between(pchar('{').left(trivia()), pchar('}'))
+        .map(Expr::Map)
+        .node("map")
+        .named("map")
+}
+
+// A `[index]` suffix, which like an operator has to stay on the line of the
+// value it indexes.
+fn index(expr: RcParser<Expr>) -> RcParser<Expr> {
+    expr.between(continues_line(pchar('[')).left(trivia()), pchar(']'))
+        .left(trivia())
+        .named("index")
+}
+
+fn indexed(array: Expr, index: Expr) -> Expr {
+    Expr::Index(Box::new(array), Box::new(index))
+}
+
+enum Suffix {
+    Index(Expr),
+    Call(Vec<Expr>),
+}
+
+fn apply(value: Expr, suffix: Suffix) -> Expr {
+    match suffix {
+        Suffix::Index(index) => indexed(value, index),
+        Suffix::Call(arguments) => Expr::Apply(Box::new(value), arguments),
+    }
+}
+
+// Any value can be indexed or called, as in `rows[0][1]` or `adder(1)(2)`.
+fn postfix<'a>(primary: RcParser<'a, Expr>, expr: RcParser<'a, Expr>) -> RcParser<'a, Expr> {
+    let suffix = choice(vec![
+        index(expr.clone()).map(Suffix::Index),
+        arguments(expr).left(trivia()).map(Suffix::Call),
+    ]);
+    primary
+        .then(suffix.many())
+        .map(|(value, suffixes)| suffixes.into_iter().fold(value, apply))
+        .named("postfix")
+}
+
+fn index_assign(expr: RcParser<Expr>) -> RcParser<Expr> {
+    string_symbol()
+        .left(trivia())
+        .then(index(expr.clone()).many1())
+        .left(pchar('=').left(trivia()))
+        .then(expr)
+        .map(|((array, mut indices), value)| {
+            let last = indices.pop().expect("many1 yields at least one index");
+            let array = indices.into_iter().fold(array, indexed);
+            Expr::SetIndex(Box::new(array), Box::new(last), Box::new(value))
+        })
+        .node("index_assign")
+        .named("index_assign")
 }
 
+// `else if` is shorthand for an `else` block holding just another `if`.
 fn condition<'a>(expr: RcParser<'a, Expr>, body: RcParser<'a, Vec<Expr>>) -> RcParser<'a, Expr> {
-    let if_ = pstring("if").ws1();
+    let mut condition = forward();
+    let if_ = pstring("if").token("keyword").left(trivia1());
     let cond = expr.clone();
+    let else_if: RcParser<'a, Expr> = condition.clone();
+    let else_ = keyword("else")
+        .token("keyword")
+        .left(trivia())
+        .right(else_if.map(|if_| vec![if_]).or(body.clone()));
 
-    if_.right(cond)
-        .then(body.clone())
-        .left(pstring("else").ws())
+    let implementation = if_
+        .right(cond)
         .then(body.clone())
+        .then(else_.optional())
         .map(|((cond, true_body), false_body)| Expr::If(Box::new(cond), true_body, false_body))
+        .node("if")
+        .named("if");
+    set_implementation(&mut condition, implementation);
+    condition
+}
+
+fn arguments(expr: RcParser<Expr>) -> RcParser<Vec<Expr>> {
+    expr.sep_by(pchar(',').left(trivia()))
+        .between(continues_line(pchar('(')).left(trivia()), pchar(')'))
 }
 
 fn function_call(expr: RcParser<Expr>) -> RcParser<Expr> {
-    let function_name = string_ident().ws();
-    //TODO implement sepBy then support multiple parameters
-    let parameters = expr.between(pchar('('), pchar(')'));
+    let function_name = string_ident().left(trivia());
 
     function_name
-        .then(parameters)
-        .map(|(name, parameters)| Expr::Call(name, vec![parameters]))
+        .then(arguments(expr))
+        .map(|(name, arguments)| Expr::Call(name, arguments))
+        .node("call")
+        .named("call")
+}
+
+fn params<'a>() -> RcParser<'a, Vec<String>> {
+    string_ident()
+        .sep_by(pchar(',').left(trivia()))
+        .between(pchar('(').left(trivia()), pchar(')'))
+}
+
+// `fn(params) { body }`, a function without a name.
+fn lambda(body: RcParser<Vec<Expr>>) -> RcParser<Expr> {
+    pstring("fn")
+        .token("keyword")
+        .left(trivia())
+        .right(params())
+        .left(trivia())
+        .then(body)
+        .map(|(params, body)| Expr::Lambda(params, body))
+        .node("lambda")
+        .named("lambda")
 }
 
 pub fn body<'a>() -> RcParser<'a, Vec<Expr>> {
     let mut body = forward();
 
     let expr: RcParser<'a, Expr> = {
+        let float_ = float();
         let int_ = int();
         let symbol = string_symbol();
         let quoted_string = quoted_string();
         let bool_ = bool();
+        let null_ = null();
 
         let mut forward = forward();
 
-        let add = add(forward.clone());
-        let subtract = subtract(forward.clone());
-        let multiply = multiply(forward.clone());
-        let divide = divide(forward.clone());
-        let modulus = modulus(forward.clone());
         let if_ = condition(forward.clone(), body.clone());
         let while_ = while_loop(forward.clone(), body.clone());
-        let equals = equals(forward.clone());
-        let lt = lt(forward.clone());
-        let gt = gt(forward.clone());
-        let and = and(forward.clone());
+        let for_ = for_loop(forward.clone(), body.clone());
+        let array = array(forward.clone());
+        let map = map(forward.clone());
+        let lambda = lambda(body.clone());
         let function_call = function_call(forward.clone());
+        let grouped: RcParser<'a, Expr> = forward.clone();
+        let parens = grouped
+            .between(pchar('(').left(trivia()), pchar(')'))
+            .node("parens")
+            .named("parens");
         let return_ = pstring("return")
-            .ws1()
+            .token("keyword")
+            .left(trivia1())
             .right(forward.clone())
-            .map(|value| Expr::Return(Box::new(value)));
+            .map(|value| Expr::Return(Box::new(value)))
+            .node("return")
+            .named("return");
 
         let assign = {
             let ident = string_ident();
-            let equal = pchar('=').ws();
+            let equal = pchar('=').left(trivia());
             let name = ident.left(equal);
 
             name.then(forward.clone())
                 .map(|(name, value)| Expr::Ident(name, Box::new(value)))
-                .ws()
+                .left(trivia())
+                .node("assign")
+                .named("assign")
         };
 
-        let parsers = vec![
-            equals,
-            lt,
-            gt,
-            and,
+        let primary = choice(vec![
             while_,
             if_,
-            assign,
+            for_,
+            // Before `int`, which would stop at the `.` or exponent.
+            float_,
             int_,
             bool_,
-            return_,
+            null_,
+            // Before `symbol`, which would take the `r` of a raw string.
+            quoted_string,
+            // Before `call`, which would read `fn(x)` as a call to `fn`.
+            lambda,
             function_call,
             symbol,
-            quoted_string,
-            add,
-            subtract,
-            multiply,
-            divide,
-            modulus,
+            parens,
+            array,
+            map,
+        ])
+        // Taking trailing comments here lets `continues_line` see past them.
+        .left(trivia())
+        .named("primary");
+
+        let operand = postfix(primary, forward.clone());
+        let parsers = vec![
+            assign,
+            index_assign(forward.clone()),
+            return_,
+            infix(operand),
         ];
-        let expr = choice(parsers).ws();
+        let expr = choice(parsers).left(trivia()).named("expr");
 
         set_implementation(&mut forward, expr);
         forward
@@ -223,8 +523,10 @@ pub fn body<'a>() -> RcParser<'a, Vec<Expr>> {
     let body_content = expr
         .clone()
         .many1()
-        .between(pchar('{').ws(), pchar('}'))
-        .ws();
+        .between(pchar('{').left(trivia()), pchar('}'))
+        .left(trivia())
+        .node("body")
+        .named("body");
 
     set_implementation(&mut body, body_content);
 
@@ -233,24 +535,304 @@ pub fn body<'a>() -> RcParser<'a, Vec<Expr>> {
 
 pub fn function<'a>() -> RcParser<'a, Function> {
     let name = pstring("fn")
-        .ws1()
+        .token("keyword")
+        .left(trivia1())
         .right(string_ident())
-        .ws()
-        .then(
-            /*string_ident().left(pchar(',').optional().ws())*/
-            string_ident().many().between(pchar('(').ws(), pchar(')'))
-        )
-        .ws(); 
-    let func = name.then(body());
-    func.map(|((name, params), body)| Function { name, params, body })
+        .left(trivia())
+        .then(params())
+        .left(trivia()); 
+    let func = doc_comment().then(name).then(body());
+    func.map(|((doc, (name, params)), body)| Function { doc, name, params, body })
+        .node("function")
+        .named("function")
 }
 
 pub fn module<'a>() -> RcParser<'a, HashMap<String, Function>> {
-    function().many1().map(|fns| {
+    trivia().right(function().many1()).map(|fns| {
         let mut fns_map = HashMap::new();
         for f in fns {
             fns_map.insert(f.name.clone(), f);
         }
         fns_map
-    } )
-}
\ No newline at end of file
+    } ).named("module")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser_combinator::cst::parse_cst;
+    use crate::parser_combinator::{ErrorKind, ParseSession};
+
+    #[test]
+    fn example_cst_is_lossless() {
+        let source = include_str!("../../example.pc");
+        let (functions, tree) = parse_cst(&module(), source).unwrap();
+
+        assert_eq!(functions.len(), 3);
+        assert_eq!(tree.text(), source);
+        let kinds: Vec<_> = tree.child_nodes().iter().map(|node| node.kind()).collect();
+        assert_eq!(kinds, vec!["function", "function", "function"]);
+        assert!(tree.tokens().iter().all(|token| token.kind() != "unknown"));
+    }
+
+    #[test]
+    fn integer_literals() {
+        let (exprs, _) = body().parse("{ 0x1F\n-0b11\n1_000 }").unwrap();
+        assert_eq!(exprs, vec![Expr::Int(31), Expr::Int(-3), Expr::Int(1000)]);
+
+        let source = "{ x = 99999999999 }";
+        let error = body().parse(source).unwrap_err();
+        assert!(error
+            .message
+            .ends_with("Integer literal 99999999999 does not fit in i32"));
+        assert_eq!(error.offset(source), 6);
+
+        let error = body().parse("{ return 0xFFFFFFFF }").unwrap_err();
+        assert!(error
+            .message
+            .ends_with("Integer literal 0xFFFFFFFF does not fit in i32"));
+    }
+
+    #[test]
+    fn float_literals() {
+        let (exprs, _) = body().parse("{ 2.25\n-1e-3\n2 * 0.5 }").unwrap();
+        let product = Expr::Multiply(Box::new(Expr::Int(2)), Box::new(Expr::Float(0.5)));
+        assert_eq!(exprs, vec![Expr::Float(2.25), Expr::Float(-0.001), product]);
+        // Not `x = 1` followed by `e` and `400`.
+        let source = "{ x = 1e400 }";
+        let error = body().parse(source).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Invalid);
+        assert!(error.message.ends_with("Float literal 1e400 is out of range"));
+        assert_eq!(error.offset(source), 6);
+    }
+
+    #[test]
+    fn string_literals() {
+        let (exprs, _) = body().parse(r#"{ "a \"b\"" r"c:\d" }"#).unwrap();
+        assert_eq!(
+            exprs,
+            vec![Expr::Str("a \"b\"".to_string()), Expr::Str(r"c:\d".to_string())]
+        );
+    }
+
+    #[test]
+    fn calls_take_comma_separated_arguments() {
+        let (exprs, _) = body().parse("{ f()\n g(1, x + 1) }").unwrap();
+        let sum = Expr::Add(Box::new(Expr::Symbol("x".to_string())), Box::new(Expr::Int(1)));
+
+        assert_eq!(
+            exprs,
+            vec![
+                Expr::Call("f".to_string(), vec![]),
+                Expr::Call("g".to_string(), vec![Expr::Int(1), sum]),
+            ]
+        );
+
+        let (function, _) = function().parse("fn add(a, b) { a }").unwrap();
+        assert_eq!(function.params, vec!["a".to_string(), "b".to_string()]);
+    }
+
+    #[test]
+    fn comments_are_whitespace() {
+        let symbol = |name: &str| Box::new(Expr::Symbol(name.to_string()));
+        let source = "{ a // note\n -1 /* one /* two */ */ b /* c */ / c }";
+        let (exprs, rest) = body().parse(source).unwrap();
+
+        assert_eq!(rest, "");
+        assert_eq!(
+            exprs,
+            vec![
+                Expr::Symbol("a".to_string()),
+                Expr::Int(-1),
+                Expr::Divide(symbol("b"), symbol("c")),
+            ]
+        );
+
+        let source = "// header\n/// Adds one.\n///\n/// Twice.\nfn/**/f(x) { x }";
+        let (module, rest) = module().parse(source).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(module["f"].doc.as_deref(), Some("Adds one.\n\nTwice."));
+
+        // Anywhere else, `///` starts an ordinary comment.
+        let source = "fn main() { /// note\n return 1 /// end\n}\n/// trailing";
+        let (module, rest) = super::module().parse(source).unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(module["main"].body, vec![Expr::Return(Box::new(Expr::Int(1)))]);
+    }
+
+    #[test]
+    fn trivia_rules_do_not_clash_with_ws() {
+        let grammar = Grammar::of(&pchar('x').ws().then(module()));
+        let ebnf = grammar.to_ebnf();
+        let rule = |name: &str| {
+            let prefix = format!("{} ", name);
+            ebnf.lines().find(|line| line.starts_with(&prefix)).unwrap().to_string()
+        };
+
+        assert!(!rule("ws").contains("comment"));
+        assert!(rule("trivia").contains("comment"));
+        assert!(rule("trivia1").contains("comment"));
+    }
+
+    #[test]
+    fn null_is_a_whole_word() {
+        let (exprs, rest) = body().parse("{ x = nullable\n null }").unwrap();
+        assert_eq!(rest, "");
+        let nullable = Box::new(Expr::Symbol("nullable".to_string()));
+        assert_eq!(exprs, vec![Expr::Ident("x".to_string(), nullable), Expr::Null]);
+    }
+
+    #[test]
+    fn keywords_are_whole_words() {
+        let symbol = |name: &str| Expr::Symbol(name.to_string());
+        let (exprs, rest) = body().parse("{ x = trueish\n falsey }").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            exprs,
+            vec![Expr::Ident("x".to_string(), Box::new(symbol("trueish"))), symbol("falsey")]
+        );
+
+        let (exprs, rest) = body().parse("{ if c { 1 } elsewhere }").unwrap();
+        assert_eq!(rest, "");
+        assert_eq!(
+            exprs,
+            vec![
+                Expr::If(Box::new(symbol("c")), vec![Expr::Int(1)], None),
+                symbol("elsewhere"),
+            ]
+        );
+
+        assert!(body().parse("{ if c { 1 } elseif d { 2 } }").is_err());
+        let (exprs, _) = body().parse("{ if c { 1 } else if d { 2 } }").unwrap();
+        assert!(matches!(&exprs[0], Expr::If(_, _, Some(else_)) if else_.len() == 1));
+    }
+
+    #[test]
+    fn arrays_and_indexing() {
+        let xs = || Expr::Symbol("xs".to_string());
+        let source = "{ xs = [1, [], f(2)]\n xs[0][1] = -xs[2]\n xs\n[3] }";
+        let (exprs, rest) = body().parse(source).unwrap();
+        assert_eq!(rest, "");
+
+        let call = Expr::Call("f".to_string(), vec![Expr::Int(2)]);
+        let array = Expr::Array(vec![Expr::Int(1), Expr::Array(vec![]), call]);
+        assert_eq!(exprs[0], Expr::Ident("xs".to_string(), Box::new(array)));
+
+        let target = indexed(xs(), Expr::Int(0));
+        let value = Expr::Negate(Box::new(indexed(xs(), Expr::Int(2))));
+        assert_eq!(
+            exprs[1],
+            Expr::SetIndex(Box::new(target), Box::new(Expr::Int(1)), Box::new(value))
+        );
+        // An index on the next line is an array literal instead.
+        assert_eq!(exprs[2..], [xs(), Expr::Array(vec![Expr::Int(3)])]);
+    }
+
+    #[test]
+    fn map_literals() {
+        let source = "{ {}\n {\"a\": 1, x: {}}[\"a\"] }";
+        let (exprs, rest) = body().parse(source).unwrap();
+        assert_eq!(rest, "");
+
+        let entries = vec![
+            (Expr::Str("a".to_string()), Expr::Int(1)),
+            (Expr::Symbol("x".to_string()), Expr::Map(vec![])),
+        ];
+        let lookup = indexed(Expr::Map(entries), Expr::Str("a".to_string()));
+        assert_eq!(exprs, vec![Expr::Map(vec![]), lookup]);
+    }
+
+    #[test]
+    fn lambdas_and_calls_on_values() {
+        let source = "{ fn(a, b) { a }(1, 2)\n f(1)(2)[0]\n (g)() }";
+        let (exprs, rest) = body().parse(source).unwrap();
+        assert_eq!(rest, "");
+
+        let lambda = Expr::Lambda(
+            vec!["a".to_string(), "b".to_string()],
+            vec![Expr::Symbol("a".to_string())],
+        );
+        let call = Expr::Call("f".to_string(), vec![Expr::Int(1)]);
+        let applied = Expr::Apply(Box::new(call), vec![Expr::Int(2)]);
+        assert_eq!(
+            exprs,
+            vec![
+                Expr::Apply(Box::new(lambda), vec![Expr::Int(1), Expr::Int(2)]),
+                indexed(applied, Expr::Int(0)),
+                Expr::Apply(Box::new(Expr::Symbol("g".to_string())), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn nested_parens_take_linear_steps() {
+        // Operands are parsed once and then extended, so nesting does not
+        // re-parse them; fully parenthesized operators used to be exponential.
+        let source = format!("{{ {}1{} }}", "(".repeat(30), " + 1)".repeat(30));
+        let session = ParseSession::new().max_steps(100_000);
+        assert!(session.parse(&body(), &source).is_ok());
+        assert!(session.steps() < 20_000, "took {} steps", session.steps());
+
+        let session = ParseSession::new().max_steps(100);
+        let error = session.parse(&body(), &source).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ResourceExhausted);
+    }
+
+    #[test]
+    fn infix_precedence_and_associativity() {
+        let (exprs, _) = body()
+            .parse("{ a = 1 + 2 * 3 - 4 % 2\n b < 1 + 1 == c && d\n (1 - 2) - 3\n !a || b >= -c }")
+            .unwrap();
+        let int = |i| Box::new(Expr::Int(i));
+        let symbol = |s: &str| Box::new(Expr::Symbol(s.to_string()));
+
+        let sum = Expr::Add(int(1), Box::new(Expr::Multiply(int(2), int(3))));
+        let value = Expr::Subtract(Box::new(sum), Box::new(Expr::Modulus(int(4), int(2))));
+        assert_eq!(exprs[0], Expr::Ident("a".to_string(), Box::new(value)));
+
+        let less = Expr::LessThan(symbol("b"), Box::new(Expr::Add(int(1), int(1))));
+        let equals = Expr::Equals(Box::new(less), symbol("c"));
+        assert_eq!(exprs[1], Expr::And(Box::new(equals), symbol("d")));
+
+        let difference = Expr::Subtract(Box::new(Expr::Subtract(int(1), int(2))), int(3));
+        assert_eq!(exprs[2], difference);
+
+        let negated = Expr::GreaterThanOrEqual(symbol("b"), Box::new(Expr::Negate(symbol("c"))));
+        let or = Expr::Or(Box::new(Expr::Not(symbol("a"))), Box::new(negated));
+        assert_eq!(exprs[3], or);
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_stack_overflow() {
+        let source = format!("{{ {}1 }}", "(".repeat(100_000));
+
+        let error = body().parse(&source).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TooDeep);
+        // Tests run on 2MB threads, so the limit is reached before the stack
+        // runs low.
+        assert!(error.message.ends_with("Maximum nesting depth of 64 exceeded"));
+        let source = format!("{{ {}1 }}", "[".repeat(100_000));
+        let error = body().parse(&source).unwrap_err();
+        assert!(error.message.ends_with("Maximum nesting depth of 64 exceeded"));
+
+        let session = ParseSession::new().max_depth(20);
+        let error = session.parse(&body(), &source).unwrap_err();
+        assert!(error.message.ends_with("Maximum nesting depth of 20 exceeded"));
+    }
+
+    #[test]
+    fn deep_nesting_parses_on_a_growable_stack() {
+        let source = format!("{{ {}1 }}", "return ".repeat(2_000));
+        let session = ParseSession::new().max_depth(5_000).grow_stack();
+
+        let (exprs, remaining) = session.parse(&body(), &source).unwrap();
+        assert_eq!(remaining, "");
+        let mut depth = 0;
+        let mut expr = &exprs[0];
+        while let Expr::Return(value) = expr {
+            depth += 1;
+            expr = value;
+        }
+        assert_eq!(depth, 2_000);
+    }
+}
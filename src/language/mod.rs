@@ -1,5 +1,6 @@
 pub mod ast;
 pub mod lang_parser;
+pub mod pretty;
 pub mod vm_emit;
 
 pub use ast::Expr;
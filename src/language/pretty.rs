@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+
+use crate::ast::*;
+use crate::parser_combinator::string::escape_string;
+
+const INDENT: &str = "    ";
+
+// Binding strength of an expression's outermost operator. Anything that
+// is not a binary operation or an atom binds loosest, so it is always
+// parenthesized as an operand.
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Ident(..)
+        | Expr::SetIndex(..)
+        | Expr::Return(_)
+        | Expr::If(..)
+        | Expr::While(..)
+        | Expr::For(..) => 0,
+        Expr::Or(..) => 1,
+        Expr::And(..) => 2,
+        Expr::Equals(..) | Expr::NotEquals(..) => 3,
+        Expr::LessThan(..)
+        | Expr::LessThanOrEqual(..)
+        | Expr::GreaterThan(..)
+        | Expr::GreaterThanOrEqual(..) => 4,
+        Expr::Add(..) | Expr::Subtract(..) => 5,
+        Expr::Multiply(..) | Expr::Divide(..) | Expr::Modulus(..) => 6,
+        Expr::Not(_) | Expr::Negate(_) => 7,
+        Expr::Index(..) | Expr::Apply(..) => 8,
+        _ => 9,
+    }
+}
+
+fn operand(expr: &Expr, min_precedence: u8, depth: usize) -> String {
+    if precedence(expr) < min_precedence {
+        format!("({})", print_expr(expr, depth))
+    } else {
+        print_expr(expr, depth)
+    }
+}
+
+// Operators associate to the left, so only a right operand of the same
+// precedence needs parentheses.
+fn binary(expr: &Expr, lhs: &Expr, operator: &str, rhs: &Expr, depth: usize) -> String {
+    let precedence = precedence(expr);
+    format!(
+        "{} {} {}",
+        operand(lhs, precedence, depth),
+        operator,
+        operand(rhs, precedence + 1, depth)
+    )
+}
+
+fn prefix(expr: &Expr, operator: &str, value: &Expr, depth: usize) -> String {
+    let value = operand(value, precedence(expr), depth);
+    // `-1` would read back as a literal.
+    if operator == "-" && value.starts_with(|c: char| c.is_ascii_digit()) {
+        format!("-({})", value)
+    } else {
+        format!("{}{}", operator, value)
+    }
+}
+
+fn print_body(body: &[Expr], depth: usize) -> String {
+    let mut text = String::from("{\n");
+    for expr in body {
+        text.push_str(&INDENT.repeat(depth + 1));
+        text.push_str(&print_expr(expr, depth + 1));
+        text.push('\n');
+    }
+    text.push_str(&INDENT.repeat(depth));
+    text.push('}');
+    text
+}
+
+pub fn print_expr(expr: &Expr, depth: usize) -> String {
+    match expr {
+        Expr::Int(i) => i.to_string(),
+        // `Debug` always keeps a `.` or exponent, so `1.0` stays a float.
+        Expr::Float(f) => format!("{:?}", f),
+        Expr::Str(s) => escape_string(s),
+        Expr::Bool(b) => b.to_string(),
+        Expr::Null => "null".to_string(),
+        Expr::Symbol(s) => s.clone(),
+        Expr::Ident(name, value) => format!("{} = {}", name, print_expr(value, depth)),
+        Expr::Call(name, parameters) => {
+            let parameters: Vec<_> = parameters
+                .iter()
+                .map(|parameter| print_expr(parameter, depth))
+                .collect();
+            format!("{}({})", name, parameters.join(", "))
+        }
+        Expr::Return(value) => format!("return {}", print_expr(value, depth)),
+        Expr::Array(items) => {
+            let items: Vec<_> = items.iter().map(|item| print_expr(item, depth)).collect();
+            format!("[{}]", items.join(", "))
+        }
+        Expr::Map(entries) => {
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|(key, value)| {
+                    format!("{}: {}", print_expr(key, depth), print_expr(value, depth))
+                })
+                .collect();
+            format!("{{{}}}", entries.join(", "))
+        }
+        Expr::Index(array, index) => format!(
+            "{}[{}]",
+            operand(array, precedence(expr), depth),
+            print_expr(index, depth)
+        ),
+        Expr::Apply(function, arguments) => {
+            let arguments: Vec<_> = arguments
+                .iter()
+                .map(|argument| print_expr(argument, depth))
+                .collect();
+            // `f(x)` would read back as a call to the function named `f`.
+            let function = match function.as_ref() {
+                Expr::Symbol(name) => format!("({})", name),
+                function => operand(function, precedence(expr), depth),
+            };
+            format!("{}({})", function, arguments.join(", "))
+        }
+        Expr::SetIndex(array, index, value) => format!(
+            "{}[{}] = {}",
+            print_expr(array, depth),
+            print_expr(index, depth),
+            print_expr(value, depth)
+        ),
+        Expr::Add(lhs, rhs) => binary(expr, lhs, "+", rhs, depth),
+        Expr::Subtract(lhs, rhs) => binary(expr, lhs, "-", rhs, depth),
+        Expr::Multiply(lhs, rhs) => binary(expr, lhs, "*", rhs, depth),
+        Expr::Divide(lhs, rhs) => binary(expr, lhs, "/", rhs, depth),
+        Expr::Modulus(lhs, rhs) => binary(expr, lhs, "%", rhs, depth),
+        Expr::Equals(lhs, rhs) => binary(expr, lhs, "==", rhs, depth),
+        Expr::NotEquals(lhs, rhs) => binary(expr, lhs, "!=", rhs, depth),
+        Expr::LessThan(lhs, rhs) => binary(expr, lhs, "<", rhs, depth),
+        Expr::LessThanOrEqual(lhs, rhs) => binary(expr, lhs, "<=", rhs, depth),
+        Expr::GreaterThan(lhs, rhs) => binary(expr, lhs, ">", rhs, depth),
+        Expr::GreaterThanOrEqual(lhs, rhs) => binary(expr, lhs, ">=", rhs, depth),
+        Expr::And(lhs, rhs) => binary(expr, lhs, "&&", rhs, depth),
+        Expr::Or(lhs, rhs) => binary(expr, lhs, "||", rhs, depth),
+        Expr::Not(value) => prefix(expr, "!", value, depth),
+        Expr::Negate(value) => prefix(expr, "-", value, depth),
+        Expr::If(cond, true_body, false_body) => {
+            let mut text = format!(
+                "if {} {}",
+                print_expr(cond, depth),
+                print_body(true_body, depth)
+            );
+            match false_body.as_deref() {
+                Some([else_if @ Expr::If(..)]) => {
+                    text.push_str(&format!(" else {}", print_expr(else_if, depth)))
+                }
+                Some(false_body) => {
+                    text.push_str(&format!(" else {}", print_body(false_body, depth)))
+                }
+                None => {}
+            }
+            text
+        }
+        Expr::While(cond, body) => format!(
+            "while {} {}",
+            print_expr(cond, depth),
+            print_body(body, depth)
+        ),
+        Expr::For(name, array, body) => format!(
+            "for {} in {} {}",
+            name,
+            print_expr(array, depth),
+            print_body(body, depth)
+        ),
+        Expr::Lambda(params, body) => {
+            format!("fn({}) {}", params.join(", "), print_body(body, depth))
+        }
+    }
+}
+
+pub fn print_function(function: &Function) -> String {
+    let mut text = String::new();
+    for line in function.doc.iter().flat_map(|doc| doc.split('\n')) {
+        match line {
+            "" => text.push_str("///\n"),
+            line => text.push_str(&format!("/// {}\n", line)),
+        }
+    }
+    text.push_str(&format!(
+        "fn {}({}) {}\n",
+        function.name,
+        function.params.join(", "),
+        print_body(&function.body, 0)
+    ));
+    text
+}
+
+/// Prints a parsed module back to source, with functions sorted by name.
+pub fn print_module(functions: &HashMap<String, Function>) -> String {
+    let mut names: Vec<_> = functions.keys().collect();
+    names.sort();
+    let functions: Vec<_> = names
+        .into_iter()
+        .map(|name| print_function(&functions[name]))
+        .collect();
+    functions.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::language::lang_parser;
+    use crate::parser_combinator::generate::Generator;
+    use crate::parser_combinator::{Grammar, Parser};
+
+    #[test]
+    fn print_example() {
+        let source = "fn main () {\n    x = (1 + 2)\n    print(x * (x - 1) - (x - 1))\n}";
+        let (module, _) = lang_parser::module().parse(source).unwrap();
+
+        let expected = "fn main() {\n    x = 1 + 2\n    print(x * (x - 1) - (x - 1))\n}\n";
+        assert_eq!(print_module(&module), expected);
+    }
+
+    #[test]
+    fn parse_print_parse_round_trip() {
+        let grammar = Grammar::of(&lang_parser::module());
+        // Random comment text would swallow the code after it.
+        let mut generator = Generator::new(&grammar, 2023)
+            .max_depth(8)
+            .weight("comment", 0.0);
+        let inputs: Vec<String> = (0..200).map(|_| generator.generate()).collect();
+
+        let module = lang_parser::module();
+        let mut parsed = 0;
+        for input in &inputs {
+            let Ok((ast, "")) = module.parse(input) else {
+                continue;
+            };
+            parsed += 1;
+            let printed = print_module(&ast);
+            let reparsed = lang_parser::module().parse(&printed);
+            assert_eq!(
+                reparsed,
+                Ok((ast, "")),
+                "generated:\n{input}\nprinted:\n{printed}"
+            );
+        }
+        assert!(parsed > inputs.len() / 2, "only {parsed} inputs parsed");
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
 
 use crate::ast::Expr;
 use crate::virtual_machine::*;
@@ -23,13 +24,16 @@ fn emit(expr: &Expr) -> Vec<Instruction> {
     let mut instructions = Vec::new();
     match expr {
         Expr::Int(i) => instructions.push(Instruction::Push(Values::Int(*i))),
+        Expr::Float(f) => instructions.push(Instruction::Push(Values::Float(*f))),
         Expr::Str(s) => instructions.push(Instruction::Push(Values::String(s.to_string()))),
         Expr::Bool(b) => instructions.push(Instruction::Push(Values::Bool(*b))),
+        Expr::Null => instructions.push(Instruction::Push(Values::Null)),
         Expr::Symbol(s) => instructions.push(Instruction::LoadLocal(s.clone())),
         Expr::Ident(s, expr) => {
             let exprs = emit(expr);
             append(&mut instructions, &exprs);
             instructions.push(Instruction::StoreLocal(s.clone()));
+            instructions.push(Instruction::Push(Values::Null));
         }
         Expr::Return(expr) => {
             let exprs = emit(expr);
@@ -37,7 +41,7 @@ fn emit(expr: &Expr) -> Vec<Instruction> {
             instructions.push(Instruction::Ret);
         }
         Expr::Call(function_name, exprs) => {
-            let exprs = emit_body(exprs);
+            let exprs = emit_values(exprs);
             append(&mut instructions, &exprs);
             instructions.push(Instruction::Call(function_name.to_string()));
         }
@@ -78,7 +82,10 @@ fn emit(expr: &Expr) -> Vec<Instruction> {
         }
         Expr::If(cond, body, else_) => {
             let body = emit_body(body);
-            let else_ = emit_body(else_);
+            let else_ = match else_ {
+                Some(else_) => emit_body(else_),
+                None => vec![Instruction::Push(Values::Null)],
+            };
 
             instructions.push(Instruction::Push(Values::Bool(true)));
             let cond = emit(cond);
@@ -96,6 +103,13 @@ fn emit(expr: &Expr) -> Vec<Instruction> {
             append(&mut instructions, &rhs);
             instructions.push(Instruction::Equal);
         }
+        Expr::NotEquals(lhs, rhs) => {
+            let lhs = emit(lhs);
+            let rhs = emit(rhs);
+            append(&mut instructions, &lhs);
+            append(&mut instructions, &rhs);
+            instructions.push(Instruction::NotEqual);
+        }
         Expr::LessThan(lhs, rhs) => {
             let lhs = emit(lhs);
             let rhs = emit(rhs);
@@ -103,6 +117,13 @@ fn emit(expr: &Expr) -> Vec<Instruction> {
             append(&mut instructions, &rhs);
             instructions.push(Instruction::Lt);
         }
+        Expr::LessThanOrEqual(lhs, rhs) => {
+            let lhs = emit(lhs);
+            let rhs = emit(rhs);
+            append(&mut instructions, &lhs);
+            append(&mut instructions, &rhs);
+            instructions.push(Instruction::Lte);
+        }
         Expr::GreaterThan(lhs, rhs) => {
             let lhs = emit(lhs);
             let rhs = emit(rhs);
@@ -112,6 +133,13 @@ fn emit(expr: &Expr) -> Vec<Instruction> {
 
             instructions.push(Instruction::Gt);
         }
+        Expr::GreaterThanOrEqual(lhs, rhs) => {
+            let lhs = emit(lhs);
+            let rhs = emit(rhs);
+            append(&mut instructions, &lhs);
+            append(&mut instructions, &rhs);
+            instructions.push(Instruction::Gte);
+        }
         Expr::And(lhs, rhs) => {
             let lhs = emit(lhs);
             let rhs = emit(rhs);
@@ -119,20 +147,267 @@ fn emit(expr: &Expr) -> Vec<Instruction> {
             append(&mut instructions, &rhs);
             instructions.push(Instruction::And);
         }
+        Expr::Or(lhs, rhs) => {
+            let lhs = emit(lhs);
+            let rhs = emit(rhs);
+            append(&mut instructions, &lhs);
+            append(&mut instructions, &rhs);
+            instructions.push(Instruction::Or);
+        }
+        Expr::Not(value) => {
+            let value = emit(value);
+            append(&mut instructions, &value);
+            instructions.push(Instruction::Not);
+        }
+        Expr::Negate(value) => {
+            let value = emit(value);
+            append(&mut instructions, &value);
+            instructions.push(Instruction::Neg);
+        }
         Expr::While(cond, body) => {
             let body = emit_body(body);
             instructions.push(Instruction::Push(Values::Bool(true)));
             let cond = emit(cond);
             append(&mut instructions, &cond);
-            instructions.push(Instruction::JumpNotEqual(cond.len() + body.len() + 3));
+            instructions.push(Instruction::JumpNotEqual(cond.len() + body.len() + 4));
             append(&mut instructions, &body);
+            instructions.push(Instruction::Pop);
             instructions.push(Instruction::JumpUnconditional(0));
+            instructions.push(Instruction::Push(Values::Null));
+        }
+        Expr::Array(items) => {
+            let exprs = emit_values(items);
+            append(&mut instructions, &exprs);
+            instructions.push(Instruction::MakeArray(items.len()));
+        }
+        Expr::Map(entries) => {
+            for (key, value) in entries {
+                let key = emit(key);
+                let value = emit(value);
+                append(&mut instructions, &key);
+                append(&mut instructions, &value);
+            }
+            instructions.push(Instruction::MakeMap(entries.len()));
+        }
+        Expr::Index(array, index) => {
+            let array = emit(array);
+            let index = emit(index);
+            append(&mut instructions, &array);
+            append(&mut instructions, &index);
+            instructions.push(Instruction::Index);
+        }
+        Expr::SetIndex(array, index, value) => {
+            let array = emit(array);
+            let index = emit(index);
+            let value = emit(value);
+            append(&mut instructions, &array);
+            append(&mut instructions, &index);
+            append(&mut instructions, &value);
+            instructions.push(Instruction::SetIndex);
+            instructions.push(Instruction::Push(Values::Null));
+        }
+        Expr::For(name, array, body) => {
+            // The array and the position in it are kept in locals that
+            // source code cannot name.
+            let array = emit(array);
+            let body = emit_body(body);
+            let (items, position) = hidden_locals(name, &body);
+
+            append(&mut instructions, &array);
+            instructions.push(Instruction::StoreLocal(items.clone()));
+            instructions.push(Instruction::Push(Values::Int(0)));
+            instructions.push(Instruction::StoreLocal(position.clone()));
+
+            let start = instructions.len();
+            instructions.push(Instruction::Push(Values::Bool(true)));
+            instructions.push(Instruction::LoadLocal(position.clone()));
+            instructions.push(Instruction::LoadLocal(items.clone()));
+            instructions.push(Instruction::Call("len".to_string()));
+            instructions.push(Instruction::Lt);
+            // Past the 4 instructions that load the item, the body, and the
+            // 6 that drop its value, advance the position and jump back.
+            let end = instructions.len() + 1 + 4 + body.len() + 6;
+            instructions.push(Instruction::JumpNotEqual(end));
+            instructions.push(Instruction::LoadLocal(items.clone()));
+            instructions.push(Instruction::LoadLocal(position.clone()));
+            instructions.push(Instruction::Index);
+            instructions.push(Instruction::StoreLocal(name.clone()));
+            append(&mut instructions, &body);
+            instructions.push(Instruction::Pop);
+            instructions.push(Instruction::LoadLocal(position.clone()));
+            instructions.push(Instruction::Push(Values::Int(1)));
+            instructions.push(Instruction::Add);
+            instructions.push(Instruction::StoreLocal(position));
+            instructions.push(Instruction::JumpUnconditional(start));
+            instructions.push(Instruction::Push(Values::Null));
+        }
+        Expr::Lambda(params, body) => {
+            // The closure copies the enclosing locals it uses as they are
+            // now, so a lambda cannot call itself through the variable it is
+            // about to be assigned to.
+            let captures = references(body)
+                .into_iter()
+                .filter(|name| !params.contains(name))
+                .collect();
+            let function = Rc::new(emit_callable(params, body));
+            instructions.push(Instruction::MakeClosure(function, captures));
+        }
+        Expr::Apply(function, arguments) => {
+            let function = emit(function);
+            let exprs = emit_values(arguments);
+            append(&mut instructions, &function);
+            append(&mut instructions, &exprs);
+            instructions.push(Instruction::CallIndirect(arguments.len()));
         }
     }
     instructions
 }
 
+// The expressions directly inside `expr`.
+fn children(expr: &Expr) -> Vec<&Expr> {
+    match expr {
+        Expr::Int(_)
+        | Expr::Float(_)
+        | Expr::Str(_)
+        | Expr::Bool(_)
+        | Expr::Null
+        | Expr::Symbol(_) => vec![],
+        Expr::Ident(_, expr) | Expr::Return(expr) | Expr::Not(expr) | Expr::Negate(expr) => {
+            vec![expr]
+        }
+        Expr::Call(_, exprs) | Expr::Array(exprs) | Expr::Lambda(_, exprs) => exprs.iter().collect(),
+        Expr::Map(entries) => entries.iter().flat_map(|(key, value)| [key, value]).collect(),
+        Expr::SetIndex(array, index, value) => vec![array, index, value],
+        Expr::Add(lhs, rhs)
+        | Expr::Subtract(lhs, rhs)
+        | Expr::Multiply(lhs, rhs)
+        | Expr::Divide(lhs, rhs)
+        | Expr::Modulus(lhs, rhs)
+        | Expr::Equals(lhs, rhs)
+        | Expr::NotEquals(lhs, rhs)
+        | Expr::LessThan(lhs, rhs)
+        | Expr::LessThanOrEqual(lhs, rhs)
+        | Expr::GreaterThan(lhs, rhs)
+        | Expr::GreaterThanOrEqual(lhs, rhs)
+        | Expr::And(lhs, rhs)
+        | Expr::Or(lhs, rhs)
+        | Expr::Index(lhs, rhs) => vec![lhs, rhs],
+        Expr::If(cond, body, else_) => std::iter::once(cond.as_ref())
+            .chain(body)
+            .chain(else_.iter().flatten())
+            .collect(),
+        Expr::While(first, exprs) | Expr::For(_, first, exprs) | Expr::Apply(first, exprs) => {
+            std::iter::once(first.as_ref()).chain(exprs).collect()
+        }
+    }
+}
+
+// `children`, for rewriting them.
+fn children_mut(expr: &mut Expr) -> Vec<&mut Expr> {
+    match expr {
+        Expr::Int(_)
+        | Expr::Float(_)
+        | Expr::Str(_)
+        | Expr::Bool(_)
+        | Expr::Null
+        | Expr::Symbol(_) => vec![],
+        Expr::Ident(_, expr) | Expr::Return(expr) | Expr::Not(expr) | Expr::Negate(expr) => {
+            vec![expr]
+        }
+        Expr::Call(_, exprs) | Expr::Array(exprs) | Expr::Lambda(_, exprs) => {
+            exprs.iter_mut().collect()
+        }
+        Expr::Map(entries) => entries
+            .iter_mut()
+            .flat_map(|(key, value)| [key, value])
+            .collect(),
+        Expr::SetIndex(array, index, value) => vec![array, index, value],
+        Expr::Add(lhs, rhs)
+        | Expr::Subtract(lhs, rhs)
+        | Expr::Multiply(lhs, rhs)
+        | Expr::Divide(lhs, rhs)
+        | Expr::Modulus(lhs, rhs)
+        | Expr::Equals(lhs, rhs)
+        | Expr::NotEquals(lhs, rhs)
+        | Expr::LessThan(lhs, rhs)
+        | Expr::LessThanOrEqual(lhs, rhs)
+        | Expr::GreaterThan(lhs, rhs)
+        | Expr::GreaterThanOrEqual(lhs, rhs)
+        | Expr::And(lhs, rhs)
+        | Expr::Or(lhs, rhs)
+        | Expr::Index(lhs, rhs) => vec![lhs, rhs],
+        Expr::If(cond, body, else_) => std::iter::once(cond.as_mut())
+            .chain(body)
+            .chain(else_.iter_mut().flatten())
+            .collect(),
+        Expr::While(first, exprs) | Expr::For(_, first, exprs) | Expr::Apply(first, exprs) => {
+            std::iter::once(first.as_mut()).chain(exprs).collect()
+        }
+    }
+}
+
+// The names a function body assigns to, which are its locals along with its
+// parameters. Lambdas inside it have locals of their own.
+fn assigned(body: &[Expr]) -> HashSet<String> {
+    fn visit(expr: &Expr, names: &mut HashSet<String>) {
+        match expr {
+            Expr::Ident(name, _) | Expr::For(name, ..) => {
+                names.insert(name.clone());
+            }
+            Expr::Lambda(..) => return,
+            _ => {}
+        }
+        children(expr).into_iter().for_each(|child| visit(child, names));
+    }
+    let mut names = HashSet::new();
+    body.iter().for_each(|expr| visit(expr, &mut names));
+    names
+}
+
+// Every name read in `body`, including inside lambdas, in first-use order.
+fn references(body: &[Expr]) -> Vec<String> {
+    fn visit(expr: &Expr, names: &mut Vec<String>) {
+        if let Expr::Symbol(name) = expr {
+            if !names.contains(name) {
+                names.push(name.clone());
+            }
+        }
+        children(expr).into_iter().for_each(|child| visit(child, names));
+    }
+    let mut names = Vec::new();
+    body.iter().for_each(|expr| visit(expr, &mut names));
+    names
+}
+
+// Names for the array and position of a loop over `name` that `body` does
+// not use, so that nested loops over the same name keep their own.
+fn hidden_locals(name: &str, body: &[Instruction]) -> (String, String) {
+    (0..)
+        .map(|n| (format!("{}#array{}", name, n), format!("{}#index{}", name, n)))
+        .find(|(items, _)| {
+            !body.iter().any(|instruction| {
+                matches!(instruction, Instruction::StoreLocal(local) if local == items)
+            })
+        })
+        .expect("some suffix is unused")
+}
+
+// Every expression leaves exactly one value on the stack. A body keeps the
+// value of its last expression and drops the others.
 fn emit_body(exprs: &[Expr]) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    for (i, e) in exprs.iter().enumerate() {
+        if i > 0 {
+            instructions.push(Instruction::Pop);
+        }
+        let instructions_to_add = emit(e);
+        append(&mut instructions, &instructions_to_add);
+    }
+    instructions
+}
+
+// Leaves the values of all of `exprs` on the stack, such as call arguments.
+fn emit_values(exprs: &[Expr]) -> Vec<Instruction> {
     let mut instructions = Vec::new();
     for e in exprs {
         let instructions_to_add = emit(e);
@@ -141,25 +416,114 @@ fn emit_body(exprs: &[Expr]) -> Vec<Instruction> {
     instructions
 }
 
+// A function that ends without `return` returns null.
+fn emit_callable(params: &[String], body: &[Expr]) -> Function {
+    let mut instructions = emit_body(body);
+    instructions.push(Instruction::Pop);
+    instructions.push(Instruction::Push(Values::Null));
+    instructions.push(Instruction::Ret);
+    Function::new(params.to_vec(), instructions)
+}
+
+/// A function that ends without `return` returns null.
 pub fn emit_function(function: &crate::ast::Function) -> Function {
-    let body = emit_body(&function.body);
-    Function::new(function.params.clone(), body) 
+    emit_callable(&function.params, &function.body)
 }
 
-pub fn emit_module(functions: HashMap<String, crate::ast::Function>) -> Module {
-    let values :Vec<_>= functions.into_iter().map(
-        |(name, function)| {
-            let body = emit_body(&function.body);
-            (name, Function::new(function.params, body))
+// Functions the VM provides, with the number of arguments they take.
+const BUILTINS: &[(&str, usize)] = &[
+    ("print", 1),
+    ("len", 1),
+    ("push", 2),
+    ("pop", 1),
+    ("keys", 1),
+    ("values", 1),
+    ("contains", 2),
+    ("remove", 2),
+];
+
+// Checks that every call in `expr` names a known function and passes it as
+// many arguments as it has parameters. A call through a local name calls the
+// function value the local holds, so it becomes an `Apply`, checked when it
+// runs.
+fn resolve_calls(
+    expr: &mut Expr,
+    arities: &HashMap<String, usize>,
+    locals: &HashSet<String>,
+) -> Result<(), String> {
+    match expr {
+        Expr::Call(name, arguments) if locals.contains(name) => {
+            let apply = Expr::Apply(
+                Box::new(Expr::Symbol(name.clone())),
+                std::mem::take(arguments),
+            );
+            *expr = apply;
         }
+        Expr::Call(name, arguments) => {
+            let Some(&arity) = arities.get(name.as_str()) else {
+                return Err(format!("Call to unknown function `{}`", name));
+            };
+            if arguments.len() != arity {
+                return Err(format!(
+                    "Function `{}` takes {} arguments but {} were given",
+                    name,
+                    arity,
+                    arguments.len()
+                ));
+            }
+        }
+        Expr::Lambda(params, body) => {
+            let mut locals = locals.clone();
+            locals.extend(params.iter().cloned());
+            locals.extend(assigned(body));
+            return resolve_body(body, arities, &locals);
+        }
+        _ => {}
+    }
+    children_mut(expr)
+        .into_iter()
+        .try_for_each(|child| resolve_calls(child, arities, locals))
+}
+
+fn resolve_body(
+    exprs: &mut [Expr],
+    arities: &HashMap<String, usize>,
+    locals: &HashSet<String>,
+) -> Result<(), String> {
+    exprs
+        .iter_mut()
+        .try_for_each(|expr| resolve_calls(expr, arities, locals))
+}
+
+pub fn emit_module(mut functions: HashMap<String, crate::ast::Function>) -> Result<Module, String> {
+    let mut arities: HashMap<String, usize> = BUILTINS
+        .iter()
+        .map(|&(name, arity)| (name.to_string(), arity))
+        .collect();
+    for (name, function) in functions.iter() {
+        // The VM always runs its own builtin, and `for` loops call `len`.
+        if arities.insert(name.clone(), function.params.len()).is_some() {
+            return Err(format!("Function `{}` has the name of a builtin", name));
+        }
+    }
+    for function in functions.values_mut() {
+        let mut locals = assigned(&function.body);
+        locals.extend(function.params.iter().cloned());
+        resolve_body(&mut function.body, &arities, &locals)?;
+    }
+
+    let values :Vec<_>= functions.into_iter().map(
+        |(name, function)| (name, emit_function(&function))
     ).collect();
-    Module::new(HashMap::from_iter(values))
+    Ok(Module::new(HashMap::from_iter(values)))
     
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::language::lang_parser;
+    use crate::parser_combinator::Parser;
 
     #[test]
     fn emit_assign() {
@@ -169,8 +533,309 @@ mod tests {
         let expected = vec![
             Instruction::Push(Values::Int(1)),
             Instruction::StoreLocal("x".to_string()),
+            Instruction::Push(Values::Null),
         ];
 
         assert_eq!(result, expected);
     }
+
+    fn run(source: &str) -> Result<Option<Values>, String> {
+        let (functions, _) = lang_parser::module()
+            .parse(source)
+            .map_err(|error| error.message)?;
+        let program = Program::new(emit_module(functions)?);
+        Ok(program.eval(program.main(), HashMap::default()))
+    }
+
+    #[test]
+    fn calls_bind_every_argument() {
+        let source = "fn sub(a, b) { return a - b }\n\
+                      fn three() { return 3 }\n\
+                      fn main() { return sub(10, sub(three(), 1)) }";
+
+        assert_eq!(run(source), Ok(Some(Values::Int(8))));
+    }
+
+    #[test]
+    fn if_else_chains_pick_one_branch() {
+        let source = "fn sign(n) {\n\
+                          return if n < 0 { 0 - 1 } else if n == 0 { 0 } else { 1 }\n\
+                      }\n\
+                      fn main() { return sign(0 - 5) * 100 + sign(0) * 10 + sign(7) }";
+
+        assert_eq!(run(source), Ok(Some(Values::Int(-99))));
+    }
+
+    #[test]
+    fn if_without_else_yields_null() {
+        let source = "fn main() { x = 1\n if x > 0 { x = 2 }\n return x }";
+        assert_eq!(run(source), Ok(Some(Values::Int(2))));
+
+        let source = "fn main() { return if 1 > 2 { 3 } }";
+        assert_eq!(run(source), Ok(Some(Values::Null)));
+
+        let source = "fn main() { return if 2 > 1 { 3 } }";
+        assert_eq!(run(source), Ok(Some(Values::Int(3))));
+    }
+
+    #[test]
+    fn comparison_operators() {
+        let cases = [
+            ("1 != 2", true),
+            ("2 != 2", false),
+            ("1 <= 1", true),
+            ("2 <= 1", false),
+            ("1 >= 2", false),
+            ("2 >= 2", true),
+        ];
+        for (expr, expected) in cases {
+            let source = format!("fn main() {{ return {} }}", expr);
+            assert_eq!(run(&source), Ok(Some(Values::Bool(expected))), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn logical_operators() {
+        let cases = [
+            ("false || true", true),
+            ("false || false", false),
+            ("!true", false),
+            ("!(1 > 2) && !false", true),
+            // `&&` binds tighter than `||`.
+            ("true || false && false", true),
+        ];
+        for (expr, expected) in cases {
+            let source = format!("fn main() {{ return {} }}", expr);
+            assert_eq!(run(&source), Ok(Some(Values::Bool(expected))), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn negation() {
+        let source = "fn main() { x = 3\n return -x * 2 - -(x - 1) }";
+        assert_eq!(run(source), Ok(Some(Values::Int(-4))));
+    }
+
+    #[test]
+    fn mixed_arithmetic_promotes_to_float() {
+        let cases = [
+            ("1 + 0.5 * 2", Values::Float(2.0)),
+            ("7 / 2", Values::Int(3)),
+            ("7 / 2.0", Values::Float(3.5)),
+            ("7.5 % 2", Values::Float(1.5)),
+            ("1 < 1.5", Values::Bool(true)),
+            ("2 == 2.0", Values::Bool(true)),
+            ("-1e-1 != 0", Values::Bool(true)),
+        ];
+        for (expr, expected) in cases {
+            let source = format!("fn main() {{ return {} }}", expr);
+            assert_eq!(run(&source), Ok(Some(expected)), "{}", expr);
+        }
+    }
+
+    #[test]
+    fn arrays_index_and_assign() {
+        let source = "fn main() {\n\
+                          grid = [[1], [2, 3]]\n\
+                          grid[1][0] = grid[0][0] + 3\n\
+                          return grid[1]\n\
+                      }";
+        let expected = Values::array(vec![Values::Int(4), Values::Int(3)]);
+        assert_eq!(run(source), Ok(Some(expected)));
+    }
+
+    #[test]
+    fn arrays_are_shared() {
+        let source = "fn fill(xs) { push(xs, 3) }\n\
+                      fn main() {\n\
+                          xs = [1, 2]\n\
+                          ys = xs\n\
+                          fill(ys)\n\
+                          last = pop(ys)\n\
+                          push(ys, last * 10)\n\
+                          return xs\n\
+                      }";
+        let expected = Values::array(vec![Values::Int(1), Values::Int(2), Values::Int(30)]);
+        assert_eq!(run(source), Ok(Some(expected)));
+    }
+
+    #[test]
+    fn for_loops_visit_every_item() {
+        let source = "fn main() {\n\
+                          total = 0\n\
+                          for x in [1, 2, 3] { for y in [x, x] { total = total + y } }\n\
+                          return total + len([])\n\
+                      }";
+        assert_eq!(run(source), Ok(Some(Values::Int(12))));
+    }
+
+    #[test]
+    fn nested_loops_over_the_same_name() {
+        let source = "fn main() {\n\
+                          t = 0\n\
+                          for x in [1, 2, 3] { for x in [10, 20] { t = t + x } }\n\
+                          return t\n\
+                      }";
+        assert_eq!(run(source), Ok(Some(Values::Int(90))));
+    }
+
+    #[test]
+    #[should_panic(expected = "Index 2 is out of bounds for an array of length 2")]
+    fn out_of_bounds_index_is_an_error() {
+        let _ = run("fn main() { xs = [1, 2]\n return xs[2] }");
+    }
+
+    #[test]
+    fn maps_keep_insertion_order() {
+        let source = "fn main() {\n\
+                          m = {\"b\": 1, 2: [], true: 3}\n\
+                          m[\"b\"] = m[\"b\"] + 1\n\
+                          m[\"a\"] = len(m[2])\n\
+                          removed = remove(m, 2)\n\
+                          return [keys(m), values(m), contains(m, 2), contains(m, true)]\n\
+                      }";
+        let string = |s: &str| Values::String(s.to_string());
+        let expected = Values::array(vec![
+            Values::array(vec![string("b"), Values::Bool(true), string("a")]),
+            Values::array(vec![Values::Int(2), Values::Int(3), Values::Int(0)]),
+            Values::Bool(false),
+            Values::Bool(true),
+        ]);
+        assert_eq!(run(source), Ok(Some(expected)));
+    }
+
+    #[test]
+    fn maps_are_shared() {
+        let source = "fn count(m, key) { m[key] = if contains(m, key) { m[key] + 1 } else { 1 } }\n\
+                      fn main() {\n\
+                          m = {}\n\
+                          for word in [\"a\", \"b\", \"a\"] { count(m, word) }\n\
+                          return m\n\
+                      }";
+        let Ok(Some(map)) = run(source) else {
+            panic!("expected a map");
+        };
+        assert_eq!(map.to_string(), "{\"a\": 2, \"b\": 1}");
+    }
+
+    #[test]
+    #[should_panic(expected = "Key \"c\" is not in the map")]
+    fn missing_key_is_an_error() {
+        let _ = run("fn main() { return {\"a\": 1}[\"c\"] }");
+    }
+
+    #[test]
+    #[should_panic(expected = "Map keys must be strings, ints or bools, not 1.5")]
+    fn float_keys_are_an_error() {
+        let _ = run("fn main() { return {1.5: 1} }");
+    }
+
+    #[test]
+    fn every_expression_has_a_value() {
+        let source = "fn nothing() { x = 1 }\n\
+                      fn main() {\n\
+                          xs = []\n\
+                          return [nothing(), push(xs, 1), while false { 1 }, for x in xs { x },\n\
+                                  if false { 1 }, if true { xs[0] = 2 }, null]\n\
+                      }";
+        assert_eq!(run(source), Ok(Some(Values::array(vec![Values::Null; 7]))));
+
+        assert_eq!(run("fn main() { 1 }"), Ok(Some(Values::Null)));
+    }
+
+    #[test]
+    fn statement_values_are_dropped() {
+        let function = emit_function(&crate::ast::Function {
+            doc: None,
+            name: "main".to_string(),
+            params: vec![],
+            body: vec![Expr::Int(1), Expr::Int(2)],
+        });
+        let expected = vec![
+            Instruction::Push(Values::Int(1)),
+            Instruction::Pop,
+            Instruction::Push(Values::Int(2)),
+            Instruction::Pop,
+            Instruction::Push(Values::Null),
+            Instruction::Ret,
+        ];
+        assert_eq!(function.instructions, expected);
+    }
+
+    #[test]
+    fn calls_are_checked_against_arity() {
+        let source = "fn add(a, b) { return a + b }\nfn main() { return add(1) }";
+        assert_eq!(
+            run(source),
+            Err("Function `add` takes 2 arguments but 1 were given".to_string())
+        );
+
+        let source = "fn main() { missing() }";
+        assert_eq!(
+            run(source),
+            Err("Call to unknown function `missing`".to_string())
+        );
+    }
+
+    #[test]
+    fn functions_cannot_replace_builtins() {
+        let source = "fn len(a) { return 42 }\nfn main() { return len([1]) }";
+        assert_eq!(
+            run(source),
+            Err("Function `len` has the name of a builtin".to_string())
+        );
+
+        for name in ["keys", "values", "contains", "remove"] {
+            let source = format!("fn {}(a, b) {{ return a + b }}\nfn main() {{ return 1 }}", name);
+            assert_eq!(
+                run(&source),
+                Err(format!("Function `{}` has the name of a builtin", name))
+            );
+        }
+    }
+
+    #[test]
+    fn closures_capture_enclosing_locals() {
+        let source = "fn adder(n) { return fn(x) { return x + n } }\n\
+                      fn main() {\n\
+                          add = adder(10)\n\
+                          n = 1\n\
+                          return [add(2), adder(1)(2), (fn() { return n })()]\n\
+                      }";
+
+        let expected = Values::array(vec![Values::Int(12), Values::Int(3), Values::Int(1)]);
+        assert_eq!(run(source), Ok(Some(expected)));
+    }
+
+    #[test]
+    fn functions_are_values() {
+        let source = "fn twice(f, x) { return f(f(x)) }\n\
+                      fn double(x) { return x * 2 }\n\
+                      fn main() {\n\
+                          items = []\n\
+                          for f in [double, fn(x) { return x - 1 }] { push(items, twice(f, 5)) }\n\
+                          return items\n\
+                      }";
+
+        let expected = Values::array(vec![Values::Int(20), Values::Int(3)]);
+        assert_eq!(run(source), Ok(Some(expected)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Function takes 1 arguments but 2 were given")]
+    fn function_values_are_checked_against_arity() {
+        let _ = run("fn main() { f = fn(x) { x }\n f(1, 2) }");
+    }
+
+    #[test]
+    #[should_panic(expected = "Negating -2147483648 overflows an int")]
+    fn negating_the_smallest_int_is_an_error() {
+        let _ = run("fn main() { x = -2147483648\n return -x }");
+    }
+
+    #[test]
+    #[should_panic(expected = "3 is not a function")]
+    fn only_functions_can_be_called() {
+        let _ = run("fn main() { f = 3\n f() }");
+    }
 }
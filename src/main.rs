@@ -1,9 +1,12 @@
 // Run as `cargo run --  --source-file example.pc --print-ast --print-il`
+// Export the language grammar with `cargo run -- --print-ebnf --railroad grammar.svg`
 
 use parser_combinator::language::lang_parser;
 use parser_combinator::language::*;
+use parser_combinator::Grammar;
 use parser_combinator::vm::*;
 use std::collections::HashMap;
+use std::io::IsTerminal;
 use std::time::Instant;
 
 use clap::*;
@@ -19,17 +22,36 @@ fn print_il(il: &[Instruction]) {
 #[command(author, version, about, long_about = None)]
 struct Args {
    #[arg(long)]
-   source_file: String,
+   source_file: Option<String>,
    #[arg(long)]
    print_ast: bool,
    #[arg(long)]
    print_il: bool,
+   #[arg(long)]
+   print_ebnf: bool,
+   /// Write railroad diagrams of the language grammar to this SVG file
+   #[arg(long)]
+   railroad: Option<String>,
 
 }
 
 fn main() -> Result<(), String> {
     let args = Args::parse();
-    let program_source = std::fs::read_to_string(args.source_file).map_err(|e| format!("{e}"))?;
+
+    if args.print_ebnf || args.railroad.is_some() {
+        let grammar = Grammar::of(&lang_parser::module());
+        if args.print_ebnf {
+            print!("{}", grammar.to_ebnf());
+        }
+        if let Some(railroad) = args.railroad {
+            std::fs::write(railroad, grammar.to_railroad_svg()).map_err(|e| format!("{e}"))?;
+        }
+    }
+
+    let Some(source_file) = args.source_file else {
+        return Ok(());
+    };
+    let program_source = std::fs::read_to_string(&source_file).map_err(|e| format!("{e}"))?;
     let program_source = program_source.as_str();
 
     let module = lang_parser::module();
@@ -41,7 +63,7 @@ fn main() -> Result<(), String> {
     match expr {
         Result::Ok((module, remaining)) => {
             let emit_start = Instant::now();
-            let module = vm_emit::emit_module(module);
+            let module = vm_emit::emit_module(module)?;
             let emit_end = Instant::now();
             let emit_time = emit_end - emit_start;
 
@@ -72,7 +94,15 @@ fn main() -> Result<(), String> {
             let run_time = run_end - run_start;
             println!("{:?} in {:?}", result, run_time);
         }
-        Result::Err(error) => println!("{}", error),
+        Result::Err(error) => {
+            // Errors only record how much input was left, so they can be
+            // rendered against the untrimmed source.
+            if std::io::stderr().is_terminal() {
+                eprint!("{}", error.render_colored(program_source, &source_file));
+            } else {
+                eprint!("{}", error.render(program_source, &source_file));
+            }
+        }
     }
     Ok (())
 }
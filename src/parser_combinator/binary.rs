@@ -0,0 +1,471 @@
+// Parsers for binary formats.
+//
+// Text parsers work on `&str` so that every position is a char boundary;
+// binary input has no such structure, so byte parsers get their own trait.
+// It mirrors `Parser` without the session: there is no CST or grammar to
+// record, and no step or depth limit. A format whose parsers recurse, such
+// as one with nested values, has to bound the nesting itself. Errors are
+// ordinary `ParseError`s whose `remaining` counts the bytes left.
+
+use std::rc::Rc;
+
+use super::error::ParseError;
+
+pub type ByteResult<'a, Output> = Result<(Output, &'a [u8]), ParseError>;
+
+pub trait ByteParser<'a> {
+    type Output;
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output>;
+    fn to_rc(self) -> RcByteParser<'a, Self::Output>;
+
+    fn map<F, Out: 'a>(&self, f: F) -> RcByteParser<'a, Out>
+    where
+        F: Fn(Self::Output) -> Out + 'a,
+        Self: Sized + 'a + Clone,
+    {
+        self.try_map(move |value| Ok(f(value)))
+    }
+
+    /// Like `map`, but `f` may reject the value. The error is positioned
+    /// where this parser started.
+    fn try_map<F, Out: 'a>(&self, f: F) -> RcByteParser<'a, Out>
+    where
+        F: Fn(Self::Output) -> Result<Out, String> + 'a,
+        Self: Sized + 'a + Clone,
+    {
+        TryMapParser {
+            f,
+            parser: self.clone().to_rc(),
+        }
+        .to_rc()
+    }
+
+    /// Runs the parser that `f` picks based on this parser's output, such
+    /// as the payload parser for an opcode or the body for a length.
+    fn flat_map<F, Out: 'a>(&self, f: F) -> RcByteParser<'a, Out>
+    where
+        F: Fn(Self::Output) -> RcByteParser<'a, Out> + 'a,
+        Self: Sized + 'a + Clone,
+    {
+        FlatMapParser {
+            f,
+            parser: self.clone().to_rc(),
+        }
+        .to_rc()
+    }
+
+    fn then<T: 'a>(&self, other: RcByteParser<'a, T>) -> RcByteParser<'a, (Self::Output, T)>
+    where
+        Self: Sized + 'a + Clone,
+    {
+        ThenParser {
+            parser_a: self.clone().to_rc(),
+            parser_b: other,
+        }
+        .to_rc()
+    }
+
+    fn left<T: 'a>(&self, other: RcByteParser<'a, T>) -> RcByteParser<'a, Self::Output>
+    where
+        Self: Sized + 'a + Clone,
+        Self::Output: 'a,
+    {
+        self.then(other).map(|(left, _)| left)
+    }
+
+    fn right<T: 'a>(&self, other: RcByteParser<'a, T>) -> RcByteParser<'a, T>
+    where
+        Self: Sized + 'a + Clone,
+        Self::Output: 'a,
+    {
+        self.then(other).map(|(_, right)| right)
+    }
+
+    /// Exactly `n` repetitions.
+    fn count(&self, n: usize) -> RcByteParser<'a, Vec<Self::Output>>
+    where
+        Self: Sized + 'a + Clone,
+    {
+        CountParser {
+            n: Some(n),
+            parser: self.clone().to_rc(),
+        }
+        .to_rc()
+    }
+
+    /// Repetitions until the parser fails or the input runs out.
+    fn many(&self) -> RcByteParser<'a, Vec<Self::Output>>
+    where
+        Self: Sized + 'a + Clone,
+    {
+        CountParser {
+            n: None,
+            parser: self.clone().to_rc(),
+        }
+        .to_rc()
+    }
+}
+
+pub type RcByteParser<'a, R> = Rc<dyn ByteParser<'a, Output = R> + 'a>;
+
+impl<'a, R> ByteParser<'a> for RcByteParser<'a, R> {
+    type Output = R;
+
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output> {
+        self.as_ref().parse(input)
+    }
+
+    fn to_rc(self) -> RcByteParser<'a, R> {
+        self
+    }
+}
+
+fn error(message: impl Into<String>, input: &[u8]) -> ParseError {
+    ParseError {
+        remaining: input.len(),
+        ..ParseError::new(message, "")
+    }
+}
+
+struct FixedParser<T, const N: usize> {
+    name: &'static str,
+    convert: fn([u8; N]) -> T,
+}
+
+impl<'a, T, const N: usize> ByteParser<'a> for FixedParser<T, N>
+where
+    T: 'a,
+{
+    type Output = T;
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output> {
+        match input.split_first_chunk::<N>() {
+            Some((bytes, remaining)) => Ok(((self.convert)(*bytes), remaining)),
+            None => Err(error(
+                format!(
+                    "Expected {} bytes for {}, found {}",
+                    N,
+                    self.name,
+                    input.len()
+                ),
+                input,
+            )),
+        }
+    }
+
+    fn to_rc(self) -> RcByteParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+}
+
+macro_rules! fixed_width {
+    ($($t:ident: $le:ident $be:ident,)*) => {
+        $(
+            #[doc = concat!("A little-endian `", stringify!($t), "`.")]
+            pub fn $le<'a>() -> RcByteParser<'a, $t> {
+                FixedParser {
+                    name: stringify!($t),
+                    convert: $t::from_le_bytes,
+                }
+                .to_rc()
+            }
+
+            #[doc = concat!("A big-endian `", stringify!($t), "`.")]
+            pub fn $be<'a>() -> RcByteParser<'a, $t> {
+                FixedParser {
+                    name: stringify!($t),
+                    convert: $t::from_be_bytes,
+                }
+                .to_rc()
+            }
+        )*
+    };
+}
+
+fixed_width! {
+    u16: le_u16 be_u16,
+    u32: le_u32 be_u32,
+    u64: le_u64 be_u64,
+    i16: le_i16 be_i16,
+    i32: le_i32 be_i32,
+    i64: le_i64 be_i64,
+    f32: le_f32 be_f32,
+    f64: le_f64 be_f64,
+}
+
+/// A single byte.
+pub fn u8<'a>() -> RcByteParser<'a, u8> {
+    FixedParser {
+        name: "u8",
+        convert: u8::from_le_bytes,
+    }
+    .to_rc()
+}
+
+/// A single byte as a signed integer.
+pub fn i8<'a>() -> RcByteParser<'a, i8> {
+    FixedParser {
+        name: "i8",
+        convert: i8::from_le_bytes,
+    }
+    .to_rc()
+}
+
+struct TagParser {
+    tag: &'static [u8],
+}
+
+impl<'a> ByteParser<'a> for TagParser {
+    type Output = &'a [u8];
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output> {
+        match input.strip_prefix(self.tag) {
+            Some(remaining) => Ok((&input[..self.tag.len()], remaining)),
+            None => Err(error(
+                format!("Expected {:?}", self.tag.escape_ascii().to_string()),
+                input,
+            )),
+        }
+    }
+
+    fn to_rc(self) -> RcByteParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+}
+
+/// Exactly the bytes of `tag`, such as a file format's magic number.
+pub fn tag<'a>(tag: &'static [u8]) -> RcByteParser<'a, &'a [u8]> {
+    TagParser { tag }.to_rc()
+}
+
+struct TakeParser {
+    n: usize,
+}
+
+impl<'a> ByteParser<'a> for TakeParser {
+    type Output = &'a [u8];
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output> {
+        if input.len() < self.n {
+            return Err(error(
+                format!("Expected {} bytes, found {}", self.n, input.len()),
+                input,
+            ));
+        }
+        Ok(input.split_at(self.n))
+    }
+
+    fn to_rc(self) -> RcByteParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+}
+
+/// The next `n` bytes.
+pub fn take<'a>(n: usize) -> RcByteParser<'a, &'a [u8]> {
+    TakeParser { n }.to_rc()
+}
+
+struct LengthPrefixedParser<'a, L, T> {
+    length: RcByteParser<'a, L>,
+    parser: RcByteParser<'a, T>,
+}
+
+impl<'a, L: TryInto<usize> + 'a, T: 'a> ByteParser<'a> for LengthPrefixedParser<'a, L, T> {
+    type Output = T;
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output> {
+        let (length, body) = self.length.parse(input)?;
+        let Some(length) = length.try_into().ok().filter(|&n| n <= body.len()) else {
+            return Err(error(
+                format!("Length prefix exceeds the {} bytes left", body.len()),
+                input,
+            ));
+        };
+        let (body, remaining) = body.split_at(length);
+        // The body is parsed on its own, so positions inside it are shifted
+        // back to count the bytes after it too.
+        let (value, rest) = self.parser.parse(body).map_err(|error| ParseError {
+            remaining: error.remaining + remaining.len(),
+            ..error
+        })?;
+        if !rest.is_empty() {
+            return Err(error(
+                format!("Length-prefixed data has {} unparsed bytes", rest.len()),
+                &input[input.len() - rest.len() - remaining.len()..],
+            ));
+        }
+        Ok((value, remaining))
+    }
+
+    fn to_rc(self) -> RcByteParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+}
+
+/// A length read by `length`, then exactly that many bytes parsed by
+/// `parser`.
+pub fn length_prefixed<'a, L: TryInto<usize> + 'a, T: 'a>(
+    length: RcByteParser<'a, L>,
+    parser: RcByteParser<'a, T>,
+) -> RcByteParser<'a, T> {
+    LengthPrefixedParser { length, parser }.to_rc()
+}
+
+struct TryMapParser<'a, F, Input> {
+    f: F,
+    parser: RcByteParser<'a, Input>,
+}
+
+impl<'a, F, Input, Output> ByteParser<'a> for TryMapParser<'a, F, Input>
+where
+    F: Fn(Input) -> Result<Output, String> + 'a,
+    Input: 'a,
+{
+    type Output = Output;
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output> {
+        let (value, remaining) = self.parser.parse(input)?;
+        match (self.f)(value) {
+            Ok(value) => Ok((value, remaining)),
+            Err(message) => Err(error(message, input)),
+        }
+    }
+
+    fn to_rc(self) -> RcByteParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+}
+
+struct FlatMapParser<'a, F, Input> {
+    f: F,
+    parser: RcByteParser<'a, Input>,
+}
+
+impl<'a, F, Input, Output> ByteParser<'a> for FlatMapParser<'a, F, Input>
+where
+    F: Fn(Input) -> RcByteParser<'a, Output> + 'a,
+    Input: 'a,
+{
+    type Output = Output;
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output> {
+        let (value, remaining) = self.parser.parse(input)?;
+        (self.f)(value).parse(remaining)
+    }
+
+    fn to_rc(self) -> RcByteParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+}
+
+struct ThenParser<'a, Output1, Output2> {
+    parser_a: RcByteParser<'a, Output1>,
+    parser_b: RcByteParser<'a, Output2>,
+}
+
+impl<'a, Output1: 'a, Output2: 'a> ByteParser<'a> for ThenParser<'a, Output1, Output2> {
+    type Output = (Output1, Output2);
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output> {
+        let (a, remaining) = self.parser_a.parse(input)?;
+        let (b, remaining) = self.parser_b.parse(remaining)?;
+        Ok(((a, b), remaining))
+    }
+
+    fn to_rc(self) -> RcByteParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+}
+
+struct CountParser<'a, Output> {
+    n: Option<usize>,
+    parser: RcByteParser<'a, Output>,
+}
+
+impl<'a, Output: 'a> ByteParser<'a> for CountParser<'a, Output> {
+    type Output = Vec<Output>;
+    fn parse(&self, input: &'a [u8]) -> ByteResult<'a, Self::Output> {
+        let mut values = Vec::new();
+        let mut remaining = input;
+        match self.n {
+            Some(n) => {
+                for _ in 0..n {
+                    let (value, rest) = self.parser.parse(remaining)?;
+                    values.push(value);
+                    remaining = rest;
+                }
+            }
+            None => {
+                while let Ok((value, rest)) = self.parser.parse(remaining) {
+                    values.push(value);
+                    if rest.len() == remaining.len() {
+                        break;
+                    }
+                    remaining = rest;
+                }
+            }
+        }
+        Ok((values, remaining))
+    }
+
+    fn to_rc(self) -> RcByteParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_width_numbers() {
+        let bytes = [0x01, 0x02, 0x03, 0x04, 0xff];
+
+        assert_eq!(le_u16().parse(&bytes), Ok((0x0201, &bytes[2..])));
+        assert_eq!(be_u32().parse(&bytes), Ok((0x01020304, &bytes[4..])));
+        assert_eq!(i8().parse(&bytes[4..]), Ok((-1, &[][..])));
+        let float = 1.5f32.to_le_bytes();
+        assert_eq!(le_f32().parse(&float), Ok((1.5, &[][..])));
+
+        let error = le_i64().parse(&bytes).unwrap_err();
+        assert_eq!(error.message, "Expected 8 bytes for i64, found 5");
+        assert_eq!(error.remaining, 5);
+    }
+
+    // A file with a magic number and a count of names, each a length byte
+    // followed by UTF-8 text.
+    fn names<'a>() -> RcByteParser<'a, Vec<&'a str>> {
+        let name = u8()
+            .flat_map(|n| take(n as usize))
+            .try_map(|bytes| std::str::from_utf8(bytes).map_err(|error| error.to_string()));
+        tag(b"NAME").right(le_u16().flat_map(move |n| name.count(n as usize)))
+    }
+
+    #[test]
+    fn tag_and_take() {
+        let file = b"NAME\x02\x00\x03abc\x02de!";
+        assert_eq!(names().parse(file), Ok((vec!["abc", "de"], &b"!"[..])));
+
+        let error = names().parse(b"NAMX").unwrap_err();
+        assert_eq!(error.message, "Expected \"NAME\"");
+
+        let error = names().parse(b"NAME\x01\x00\x05ab").unwrap_err();
+        assert_eq!(error.message, "Expected 5 bytes, found 2");
+        assert_eq!(error.remaining, 2);
+    }
+
+    #[test]
+    fn length_prefixed_body_must_be_consumed() {
+        let pair = length_prefixed(u8(), le_u16().count(2));
+        assert_eq!(
+            pair.parse(b"\x04\x01\x00\x02\x00\x09"),
+            Ok((vec![1, 2], &b"\x09"[..]))
+        );
+
+        let error = pair.parse(b"\x03\x01\x00\x07\x09").unwrap_err();
+        assert_eq!(error.message, "Expected 2 bytes for u16, found 1");
+        assert_eq!(error.remaining, 2);
+
+        let error = length_prefixed(u8(), le_u16())
+            .parse(b"\x03\x01\x00\x07\x09")
+            .unwrap_err();
+        assert_eq!(error.message, "Length-prefixed data has 1 unparsed bytes");
+        assert_eq!(error.remaining, 2);
+
+        let error = pair.parse(b"\x09\x01").unwrap_err();
+        assert_eq!(error.message, "Length prefix exceeds the 1 bytes left");
+    }
+}
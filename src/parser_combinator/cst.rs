@@ -0,0 +1,408 @@
+// Lossless concrete syntax trees.
+//
+// In CST mode every leaf parser records the text it consumes as a token
+// event, `Parser::token` merges the tokens of a sub-parser into one, and
+// `Parser::node` brackets its tokens with start/finish events. Backtracking
+// combinators roll back events from alternatives that failed, so the events
+// left at the end of a parse describe exactly one tree.
+//
+// The tree is built in two layers: immutable green nodes that only know
+// their kind and text, and red `SyntaxNode`s built on demand that add
+// absolute spans and parent links. Text not claimed by any token (parsers
+// that do not record events, or input left after the parse) is kept as
+// `unknown` / `unparsed` tokens, so the tree always reproduces the input.
+
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+use super::error::ParseError;
+use super::parser::Parser;
+use super::session::ParseSession;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Event {
+    Start(&'static str),
+    Finish,
+    Token {
+        kind: &'static str,
+        start: usize,
+        end: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreenToken {
+    pub kind: &'static str,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GreenElement {
+    Node(Rc<GreenNode>),
+    Token(Rc<GreenToken>),
+}
+
+impl GreenElement {
+    pub fn len(&self) -> usize {
+        match self {
+            GreenElement::Node(node) => node.len(),
+            GreenElement::Token(token) => token.text.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GreenNode {
+    pub kind: &'static str,
+    children: Vec<GreenElement>,
+    len: usize,
+}
+
+impl GreenNode {
+    pub fn new(kind: &'static str, children: Vec<GreenElement>) -> Self {
+        let len = children.iter().map(GreenElement::len).sum();
+        GreenNode {
+            kind,
+            children,
+            len,
+        }
+    }
+
+    pub fn children(&self) -> &[GreenElement] {
+        &self.children
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn text(&self) -> String {
+        let mut text = String::with_capacity(self.len);
+        self.write_text(&mut text);
+        text
+    }
+
+    fn write_text(&self, text: &mut String) {
+        for child in &self.children {
+            match child {
+                GreenElement::Node(node) => node.write_text(text),
+                GreenElement::Token(token) => text.push_str(&token.text),
+            }
+        }
+    }
+
+    /// A copy of this node with child `index` replaced. Untouched children
+    /// are shared with the original.
+    pub fn replace_child(&self, index: usize, child: GreenElement) -> GreenNode {
+        let mut children = self.children.clone();
+        children[index] = child;
+        GreenNode::new(self.kind, children)
+    }
+}
+
+struct NodeData {
+    green: Rc<GreenNode>,
+    offset: usize,
+    parent: Option<SyntaxNode>,
+}
+
+/// A node of the concrete syntax tree, positioned within the source.
+#[derive(Clone)]
+pub struct SyntaxNode(Rc<NodeData>);
+
+#[derive(Clone)]
+pub struct SyntaxToken {
+    green: Rc<GreenToken>,
+    offset: usize,
+    parent: SyntaxNode,
+}
+
+#[derive(Clone, Debug)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(SyntaxToken),
+}
+
+impl SyntaxNode {
+    pub fn new_root(green: Rc<GreenNode>) -> Self {
+        SyntaxNode(Rc::new(NodeData {
+            green,
+            offset: 0,
+            parent: None,
+        }))
+    }
+
+    pub fn kind(&self) -> &'static str {
+        self.0.green.kind
+    }
+
+    pub fn green(&self) -> &Rc<GreenNode> {
+        &self.0.green
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        self.0.offset..self.0.offset + self.0.green.len()
+    }
+
+    pub fn text(&self) -> String {
+        self.0.green.text()
+    }
+
+    pub fn parent(&self) -> Option<SyntaxNode> {
+        self.0.parent.clone()
+    }
+
+    pub fn children(&self) -> Vec<SyntaxElement> {
+        let mut offset = self.0.offset;
+        let mut children = Vec::new();
+        for child in self.0.green.children() {
+            let element = match child {
+                GreenElement::Node(green) => SyntaxElement::Node(SyntaxNode(Rc::new(NodeData {
+                    green: green.clone(),
+                    offset,
+                    parent: Some(self.clone()),
+                }))),
+                GreenElement::Token(green) => SyntaxElement::Token(SyntaxToken {
+                    green: green.clone(),
+                    offset,
+                    parent: self.clone(),
+                }),
+            };
+            offset += child.len();
+            children.push(element);
+        }
+        children
+    }
+
+    pub fn child_nodes(&self) -> Vec<SyntaxNode> {
+        self.children()
+            .into_iter()
+            .filter_map(|child| match child {
+                SyntaxElement::Node(node) => Some(node),
+                SyntaxElement::Token(_) => None,
+            })
+            .collect()
+    }
+
+    /// Every token below this node, in source order.
+    pub fn tokens(&self) -> Vec<SyntaxToken> {
+        let mut tokens = Vec::new();
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(node) => tokens.extend(node.tokens()),
+                SyntaxElement::Token(token) => tokens.push(token),
+            }
+        }
+        tokens
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let span = self.span();
+        writeln!(
+            f,
+            "{:indent$}{}@{}..{}",
+            "",
+            self.kind(),
+            span.start,
+            span.end,
+            indent = depth * 2
+        )?;
+        for child in self.children() {
+            match child {
+                SyntaxElement::Node(node) => node.fmt_tree(f, depth + 1)?,
+                SyntaxElement::Token(token) => {
+                    writeln!(f, "{:indent$}{:?}", "", token, indent = (depth + 1) * 2)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SyntaxNode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_tree(f, 0)
+    }
+}
+
+impl SyntaxToken {
+    pub fn kind(&self) -> &'static str {
+        self.green.kind
+    }
+
+    pub fn text(&self) -> &str {
+        &self.green.text
+    }
+
+    pub fn span(&self) -> Range<usize> {
+        self.offset..self.offset + self.green.text.len()
+    }
+
+    pub fn parent(&self) -> SyntaxNode {
+        self.parent.clone()
+    }
+}
+
+impl fmt::Debug for SyntaxToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let span = self.span();
+        write!(
+            f,
+            "{}@{}..{} {:?}",
+            self.kind(),
+            span.start,
+            span.end,
+            self.text()
+        )
+    }
+}
+
+fn token(kind: &'static str, text: &str) -> GreenElement {
+    GreenElement::Token(Rc::new(GreenToken {
+        kind,
+        text: text.to_string(),
+    }))
+}
+
+fn build(kind: &'static str, source: &str, events: Vec<Event>) -> GreenNode {
+    let mut stack: Vec<(&'static str, Vec<GreenElement>)> = vec![(kind, Vec::new())];
+    let mut position = 0;
+    for event in events {
+        match event {
+            Event::Start(kind) => stack.push((kind, Vec::new())),
+            Event::Finish => {
+                if stack.len() > 1 {
+                    let (kind, children) = stack.pop().unwrap();
+                    let node = GreenNode::new(kind, children);
+                    stack
+                        .last_mut()
+                        .unwrap()
+                        .1
+                        .push(GreenElement::Node(Rc::new(node)));
+                }
+            }
+            Event::Token { kind, start, end } => {
+                let children = &mut stack.last_mut().unwrap().1;
+                if start > position {
+                    children.push(token("unknown", &source[position..start]));
+                }
+                if start >= position {
+                    children.push(token(kind, &source[start..end]));
+                    position = end;
+                }
+            }
+        }
+    }
+    while stack.len() > 1 {
+        let (kind, children) = stack.pop().unwrap();
+        let node = GreenNode::new(kind, children);
+        stack
+            .last_mut()
+            .unwrap()
+            .1
+            .push(GreenElement::Node(Rc::new(node)));
+    }
+    let (kind, mut children) = stack.pop().unwrap();
+    if position < source.len() {
+        children.push(token("unparsed", &source[position..]));
+    }
+    GreenNode::new(kind, children)
+}
+
+/// Parses `input` in CST mode and returns the parser's output together with
+/// a `root` node covering all of the input, including anything left unparsed.
+pub fn parse_cst<'a, P>(parser: &P, input: &'a str) -> Result<(P::Output, SyntaxNode), ParseError>
+where
+    P: Parser<'a> + ?Sized,
+{
+    let session = ParseSession::new().with_cst();
+    let (output, _) = session.parse(parser, input)?;
+    let green = build("root", input, session.take_events());
+    Ok((output, SyntaxNode::new_root(Rc::new(green))))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser_combinator::parser::*;
+
+    fn assignment<'a>() -> RcParser<'a, (String, &'a str)> {
+        let name = pattern("[a-z]+").token("name").ws();
+        let value = pattern("[0-9]+").token("number").ws();
+        name.map(|name| name.to_string())
+            .left(pchar('=').ws())
+            .then(value)
+            .node("assign")
+    }
+
+    #[test]
+    fn cst_is_lossless() {
+        let source = "x  =\t42 \n";
+        let (output, tree) = parse_cst(&assignment(), source).unwrap();
+
+        assert_eq!(output, ("x".to_string(), "42"));
+        assert_eq!(tree.text(), source);
+        assert_eq!(
+            format!("{:?}", tree),
+            concat!(
+                "root@0..9\n",
+                "  assign@0..9\n",
+                "    name@0..1 \"x\"\n",
+                "    whitespace@1..3 \"  \"\n",
+                "    text@3..4 \"=\"\n",
+                "    whitespace@4..5 \"\\t\"\n",
+                "    number@5..7 \"42\"\n",
+                "    whitespace@7..9 \" \\n\"\n",
+            )
+        );
+    }
+
+    #[test]
+    fn cst_drops_backtracked_tokens() {
+        let keyword = pstring("let").token("keyword").left(pchar('!'));
+        let parser = keyword
+            .map(|_| ())
+            .or(pattern("[a-z]+").token("name").map(|_| ()));
+        let (_, tree) = parse_cst(&parser, "letter").unwrap();
+
+        let tokens: Vec<_> = tree
+            .tokens()
+            .iter()
+            .map(|t| (t.kind(), t.text().to_string()))
+            .collect();
+        assert_eq!(tokens, vec![("name", "letter".to_string())]);
+    }
+
+    #[test]
+    fn cst_keeps_unparsed_input() {
+        let (_, tree) = parse_cst(&pchar('a'), "abc").unwrap();
+
+        assert_eq!(tree.text(), "abc");
+        assert_eq!(tree.tokens()[1].kind(), "unparsed");
+        assert_eq!(tree.tokens()[1].span(), 1..3);
+    }
+
+    #[test]
+    fn red_nodes_know_spans_and_parents() {
+        let (_, tree) = parse_cst(&assignment(), "ab = 1").unwrap();
+        let assign = &tree.child_nodes()[0];
+        let number = assign
+            .tokens()
+            .into_iter()
+            .find(|t| t.kind() == "number")
+            .unwrap();
+
+        assert_eq!(number.span(), 5..6);
+        assert_eq!(number.parent().kind(), "assign");
+        assert_eq!(assign.parent().unwrap().kind(), "root");
+    }
+}
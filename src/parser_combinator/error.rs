@@ -0,0 +1,347 @@
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The input did not match; alternatives may still be tried.
+    Mismatch,
+    /// The session's step budget or deadline ran out. The parse is aborted.
+    ResourceExhausted,
+    /// Recursive parsers nested deeper than the session allows.
+    TooDeep,
+    /// The input matched but was rejected, such as a literal that is out of
+    /// range. Alternatives are not tried, since none would be right.
+    Invalid,
+}
+
+/// Something that would have been accepted where a parse failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expected {
+    Char(char),
+    String(&'static str),
+    Pattern(String),
+    Rule(&'static str),
+}
+
+impl fmt::Display for Expected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expected::Char(c) => write!(f, "{:?}", c),
+            Expected::String(s) => write!(f, "{:?}", s),
+            Expected::Pattern(p) => write!(f, "/{}/", p),
+            Expected::Rule(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub kind: ErrorKind,
+    pub message: String,
+    /// Number of bytes of input that were left when the error was raised.
+    /// Parsers only ever see suffixes of the source, so this is enough to
+    /// recover the position once the original source is known.
+    pub remaining: usize,
+    /// What would have been accepted at the error position.
+    pub expected: Vec<Expected>,
+    /// Context for the error, innermost first, such as the rules it
+    /// occurred in.
+    pub notes: Vec<String>,
+}
+
+impl ParseError {
+    pub fn new(message: impl Into<String>, input: &str) -> Self {
+        ParseError {
+            kind: ErrorKind::Mismatch,
+            message: message.into(),
+            remaining: input.len(),
+            expected: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_expected(mut self, expected: Expected) -> Self {
+        self.expected.push(expected);
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn resource_exhausted(message: impl Into<String>, input: &str) -> Self {
+        ParseError {
+            kind: ErrorKind::ResourceExhausted,
+            ..ParseError::new(message, input)
+        }
+    }
+
+    pub fn too_deep(message: impl Into<String>, input: &str) -> Self {
+        ParseError {
+            kind: ErrorKind::TooDeep,
+            ..ParseError::new(message, input)
+        }
+    }
+
+    pub fn invalid(message: impl Into<String>, input: &str) -> Self {
+        ParseError {
+            kind: ErrorKind::Invalid,
+            ..ParseError::new(message, input)
+        }
+    }
+
+    /// Fatal errors abort the whole parse instead of letting combinators
+    /// such as `choice` or `many` backtrack past them.
+    pub fn is_fatal(&self) -> bool {
+        self.kind != ErrorKind::Mismatch
+    }
+
+    /// Byte offset of the error within `source`.
+    pub fn offset(&self, source: &str) -> usize {
+        source.len().saturating_sub(self.remaining)
+    }
+
+    /// 1-based line and column of the error within `source`.
+    pub fn line_col(&self, source: &str) -> (usize, usize) {
+        let offset = self.offset(source);
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let line_start = before.rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let column = before[line_start..].chars().count() + 1;
+        (line, column)
+    }
+
+    /// Whichever of the two errors got further into the input. If both got
+    /// equally far, `self` with the expectations of both.
+    pub fn furthest(mut self, other: ParseError) -> Self {
+        if other.remaining < self.remaining {
+            return other;
+        }
+        if other.remaining == self.remaining {
+            for expected in other.expected {
+                if !self.expected.contains(&expected) {
+                    self.expected.push(expected);
+                }
+            }
+        }
+        self
+    }
+
+    // A rule that fails where it starts was simply not there, so it is
+    // expected as a whole; one that fails further in gives context.
+    pub(crate) fn in_rule(mut self, name: &'static str, input: &str) -> Self {
+        if self.is_fatal() {
+            return self;
+        }
+        if self.remaining == input.len() {
+            self.expected.clear();
+            self.expected.push(Expected::Rule(name));
+        } else {
+            let note = format!("while parsing {}", name);
+            if !self.notes.contains(&note) {
+                self.notes.push(note);
+            }
+        }
+        self
+    }
+
+    pub(crate) fn prefixed(self, prefix: &str) -> Self {
+        ParseError {
+            message: format!("{} : {}", prefix, self.message),
+            ..self
+        }
+    }
+}
+
+const MAX_EXPECTED: usize = 8;
+
+// The text the caret underline covers: a whole word, or a single character.
+fn offending(rest: &str) -> &str {
+    let word = rest
+        .char_indices()
+        .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+        .map_or(rest.len(), |(i, _)| i);
+    if word > 0 {
+        &rest[..word]
+    } else {
+        &rest[..rest.chars().next().map_or(0, char::len_utf8)]
+    }
+}
+
+fn one_of(items: &[Expected]) -> String {
+    let items: Vec<String> = items.iter().map(Expected::to_string).collect();
+    let shown = items.len().min(MAX_EXPECTED);
+    let mut text = items[..shown - 1].join(", ");
+    if items.len() > shown {
+        text.push_str(&format!(
+            ", {} or {} more",
+            items[shown - 1],
+            items.len() - shown
+        ));
+    } else if shown > 1 {
+        text.push_str(&format!(" or {}", items[shown - 1]));
+    } else {
+        text.push_str(&items[0]);
+    }
+    text
+}
+
+struct Style {
+    error: &'static str,
+    gutter: &'static str,
+    bold: &'static str,
+    reset: &'static str,
+}
+
+impl ParseError {
+    /// A compiler-style report of the error: a `file:line:col` header, the
+    /// offending source line underlined with carets, what was expected, and
+    /// any notes.
+    pub fn render(&self, source: &str, filename: &str) -> String {
+        self.render_with(source, filename, false)
+    }
+
+    /// Like `render`, with ANSI colours for terminals.
+    pub fn render_colored(&self, source: &str, filename: &str) -> String {
+        self.render_with(source, filename, true)
+    }
+
+    fn render_with(&self, source: &str, filename: &str, colored: bool) -> String {
+        let style = if colored {
+            Style {
+                error: "\x1b[1;31m",
+                gutter: "\x1b[1;34m",
+                bold: "\x1b[1m",
+                reset: "\x1b[0m",
+            }
+        } else {
+            Style {
+                error: "",
+                gutter: "",
+                bold: "",
+                reset: "",
+            }
+        };
+
+        let offset = self.offset(source);
+        let (line, column) = self.line_col(source);
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[offset..]
+            .find('\n')
+            .map_or(source.len(), |i| offset + i);
+        let line_text = source[line_start..line_end].trim_end_matches('\r');
+        let rest = &source[offset..line_start + line_text.len()];
+        let token = offending(rest);
+
+        let summary = if self.expected.is_empty() {
+            self.message.clone()
+        } else if offset == source.len() {
+            "unexpected end of input".to_string()
+        } else if token.is_empty() {
+            "unexpected end of line".to_string()
+        } else {
+            format!("unexpected {:?}", token)
+        };
+
+        // Keep tabs in the padding so the carets line up with the source.
+        let padding: String = source[line_start..offset]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let carets = "^".repeat(token.chars().count().max(1));
+        let number = line.to_string();
+        let gutter = " ".repeat(number.len());
+
+        let mut report = String::new();
+        report.push_str(&format!(
+            "{}error{}{}: {}{}\n",
+            style.error, style.reset, style.bold, summary, style.reset
+        ));
+        report.push_str(&format!(
+            "{}{}-->{} {}:{}:{}\n",
+            gutter, style.gutter, style.reset, filename, line, column
+        ));
+        report.push_str(&format!("{} {}|{}\n", gutter, style.gutter, style.reset));
+        report.push_str(&format!(
+            "{}{} |{} {}\n",
+            style.gutter, number, style.reset, line_text
+        ));
+        report.push_str(&format!(
+            "{} {}|{} {}{}{}{}",
+            gutter, style.gutter, style.reset, padding, style.error, carets, style.reset
+        ));
+        if !self.expected.is_empty() {
+            report.push_str(&format!(
+                " {}expected {}{}",
+                style.error,
+                one_of(&self.expected),
+                style.reset
+            ));
+        }
+        report.push('\n');
+        for note in &self.notes {
+            report.push_str(&format!(
+                "{} {}={} {}note{}: {}\n",
+                gutter, style.gutter, style.reset, style.bold, style.reset, note
+            ));
+        }
+        report
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_col_of_error() {
+        let source = "fn main() {\n    x = @\n}";
+        let error = ParseError::new("Unexpected @", &source[20..]);
+
+        assert_eq!(error.offset(source), 20);
+        assert_eq!(error.line_col(source), (2, 9));
+    }
+
+    #[test]
+    fn render_report() {
+        let source = "fn main() {\n    x = @\n}";
+        let error = ParseError::new("Expected int", &source[20..])
+            .with_expected(Expected::Rule("int"))
+            .with_expected(Expected::Rule("string"))
+            .with_expected(Expected::Rule("identifier"))
+            .with_note("while parsing expr");
+
+        assert_eq!(
+            error.render(source, "main.pc"),
+            concat!(
+                "error: unexpected \"@\"\n",
+                " --> main.pc:2:9\n",
+                "  |\n",
+                "2 |     x = @\n",
+                "  |         ^ expected int, string or identifier\n",
+                "  = note: while parsing expr\n",
+            )
+        );
+    }
+
+    #[test]
+    fn render_custom_message_underlines_word() {
+        let source = "x = 300";
+        let error = ParseError::new("Integer literal 300 does not fit in u8", &source[4..]);
+
+        let report = error.render(source, "<input>");
+        assert!(report.starts_with("error: Integer literal 300 does not fit in u8\n"));
+        assert!(report.contains("1 | x = 300\n  |     ^^^\n"));
+        assert!(error
+            .render_colored(source, "<input>")
+            .contains("\x1b[1;31m^^^"));
+    }
+}
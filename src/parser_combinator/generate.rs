@@ -0,0 +1,279 @@
+// Generates random inputs from a `Grammar`, for property testing parsers.
+//
+// Output is fully determined by the seed. Recursion is bounded by
+// `max_depth`: once a derivation is that many rules deep, only alternatives
+// that can finish within the remaining depth are picked, and repetitions and
+// optional items are left out where possible.
+
+use std::collections::HashMap;
+
+use super::grammar::{Grammar, Syntax};
+use super::regex::Regex;
+pub use super::rng::Rng;
+
+const UNBOUNDED: usize = usize::MAX;
+
+pub struct Generator<'g> {
+    grammar: &'g Grammar,
+    rng: Rng,
+    max_depth: usize,
+    max_repeat: usize,
+    weights: HashMap<String, f64>,
+    min_depths: HashMap<String, usize>,
+}
+
+impl<'g> Generator<'g> {
+    pub fn new(grammar: &'g Grammar, seed: u64) -> Self {
+        Generator {
+            grammar,
+            rng: Rng::new(seed),
+            max_depth: 8,
+            max_repeat: 3,
+            weights: HashMap::new(),
+            min_depths: min_depths(grammar),
+        }
+    }
+
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Upper bound on the number of items produced for `*` and `+`.
+    pub fn max_repeat(mut self, max_repeat: usize) -> Self {
+        self.max_repeat = max_repeat.max(1);
+        self
+    }
+
+    /// Makes alternatives that refer to `rule` more (or less) likely to be
+    /// picked. Alternatives default to a weight of 1.
+    pub fn weight(mut self, rule: &str, weight: f64) -> Self {
+        self.weights.insert(rule.to_string(), weight);
+        self
+    }
+
+    /// Generates an input for the start rule.
+    pub fn generate(&mut self) -> String {
+        let mut output = String::new();
+        if let Some(start) = self.grammar.rules.first() {
+            self.emit(&start.syntax, 1, &mut output);
+        }
+        output
+    }
+
+    fn min_depth(&self, syntax: &Syntax) -> usize {
+        syntax_min_depth(syntax, &self.min_depths)
+    }
+
+    fn fits(&self, syntax: &Syntax, depth: usize) -> bool {
+        self.min_depth(syntax).saturating_add(depth) <= self.max_depth
+    }
+
+    // The weight of the first rule an alternative refers to, if any.
+    fn alternative_weight(&self, syntax: &Syntax) -> f64 {
+        let items = match syntax {
+            Syntax::Sequence(items) => items.as_slice(),
+            syntax => std::slice::from_ref(syntax),
+        };
+        items
+            .iter()
+            .find_map(|item| match item {
+                Syntax::Rule(name) => Some(*self.weights.get(name).unwrap_or(&1.0)),
+                _ => None,
+            })
+            .unwrap_or(1.0)
+    }
+
+    fn repeat_count(&mut self, min: usize, item: &Syntax, depth: usize) -> usize {
+        if !self.fits(item, depth) {
+            return min;
+        }
+        min + self.rng.below(self.max_repeat - min + 1)
+    }
+
+    fn emit(&mut self, syntax: &Syntax, depth: usize, output: &mut String) {
+        match syntax {
+            Syntax::Char(c) => output.push(*c),
+            Syntax::String(s) => output.push_str(s),
+            Syntax::Pattern(pattern) => {
+                if let Ok(regex) = Regex::new(pattern) {
+                    output.push_str(&regex.sample(&mut self.rng));
+                }
+            }
+            Syntax::Sequence(items) => {
+                for item in items {
+                    self.emit(item, depth, output);
+                }
+            }
+            Syntax::Choice(items) => {
+                let mut candidates: Vec<&Syntax> =
+                    items.iter().filter(|item| self.fits(item, depth)).collect();
+                if candidates.is_empty() {
+                    let shallowest = items.iter().map(|item| self.min_depth(item)).min();
+                    candidates = items
+                        .iter()
+                        .filter(|item| Some(self.min_depth(item)) == shallowest)
+                        .collect();
+                }
+                let weights: Vec<f64> = candidates
+                    .iter()
+                    .map(|item| self.alternative_weight(item).max(0.0))
+                    .collect();
+                let total: f64 = weights.iter().sum();
+                let mut pick = self.rng.unit() * total;
+                // A choice with no alternatives has nothing to emit.
+                let Some(&last) = candidates.last() else {
+                    return;
+                };
+                let mut chosen = last;
+                for (candidate, weight) in candidates.iter().zip(weights) {
+                    if pick < weight {
+                        chosen = candidate;
+                        break;
+                    }
+                    pick -= weight;
+                }
+                self.emit(chosen, depth, output);
+            }
+            Syntax::Optional(item) => {
+                if self.fits(item, depth) && self.rng.below(2) == 0 {
+                    self.emit(item, depth, output);
+                }
+            }
+            Syntax::Many(item) => {
+                for _ in 0..self.repeat_count(0, item, depth) {
+                    self.emit(item, depth, output);
+                }
+            }
+            Syntax::Many1(item) => {
+                for _ in 0..self.repeat_count(1, item, depth) {
+                    self.emit(item, depth, output);
+                }
+            }
+            Syntax::Rule(name) => {
+                if let Some(rule) = self.grammar.rule(name) {
+                    self.emit(rule, depth + 1, output);
+                }
+            }
+            Syntax::Opaque => {}
+        }
+    }
+}
+
+// The least number of nested rules needed to derive each rule, found by
+// iterating to a fixpoint. Rules that can never terminate stay UNBOUNDED.
+fn min_depths(grammar: &Grammar) -> HashMap<String, usize> {
+    let mut depths: HashMap<String, usize> = grammar
+        .rules
+        .iter()
+        .map(|rule| (rule.name.clone(), UNBOUNDED))
+        .collect();
+    loop {
+        let mut changed = false;
+        for rule in &grammar.rules {
+            let depth = syntax_min_depth(&rule.syntax, &depths);
+            if depth < depths[&rule.name] {
+                depths.insert(rule.name.clone(), depth);
+                changed = true;
+            }
+        }
+        if !changed {
+            return depths;
+        }
+    }
+}
+
+fn syntax_min_depth(syntax: &Syntax, depths: &HashMap<String, usize>) -> usize {
+    match syntax {
+        Syntax::Rule(name) => depths
+            .get(name)
+            .copied()
+            .unwrap_or(UNBOUNDED)
+            .saturating_add(1),
+        Syntax::Sequence(items) => items
+            .iter()
+            .map(|item| syntax_min_depth(item, depths))
+            .max()
+            .unwrap_or(0),
+        Syntax::Choice(items) => items
+            .iter()
+            .map(|item| syntax_min_depth(item, depths))
+            .min()
+            .unwrap_or(UNBOUNDED),
+        Syntax::Many1(item) => syntax_min_depth(item, depths),
+        Syntax::Optional(_) | Syntax::Many(_) => 0,
+        Syntax::Char(_) | Syntax::String(_) | Syntax::Pattern(_) | Syntax::Opaque => 0,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser_combinator::parser::*;
+
+    fn nested_parens() -> Grammar {
+        let mut nested = forward();
+        let inner: RcParser<char> = nested.clone();
+        let parens = inner.between(pchar('('), pchar(')'));
+        set_implementation(&mut nested, parens.or(pchar('x')).named("nested"));
+        Grammar::of(nested.as_ref())
+    }
+
+    #[test]
+    fn same_seed_same_output() {
+        let grammar = nested_parens();
+        let first: Vec<String> = {
+            let mut generator = Generator::new(&grammar, 42);
+            (0..10).map(|_| generator.generate()).collect()
+        };
+        let second: Vec<String> = {
+            let mut generator = Generator::new(&grammar, 42);
+            (0..10).map(|_| generator.generate()).collect()
+        };
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn respects_max_depth() {
+        let grammar = nested_parens();
+        let mut generator = Generator::new(&grammar, 7)
+            .max_depth(3)
+            .weight("nested", 100.0);
+        let inputs: Vec<String> = (0..50).map(|_| generator.generate()).collect();
+        for input in &inputs {
+            assert!(input.matches('(').count() <= 2, "{input}");
+        }
+        assert!(inputs.iter().any(|input| input == "((x))"));
+    }
+
+    fn number_list<'a>() -> RcParser<'a, (&'a str, Vec<&'a str>)> {
+        let number = pattern(r"-?[1-9][0-9]*(\.[0-9]+)?").named("number");
+        number
+            .clone()
+            .then(pstring(", ").right(number).many())
+            .between(pchar('['), pchar(']'))
+    }
+
+    #[test]
+    fn generated_inputs_parse() {
+        let grammar = Grammar::of(&number_list());
+        let mut generator = Generator::new(&grammar, 1);
+        let inputs: Vec<String> = (0..100).map(|_| generator.generate()).collect();
+
+        let list = number_list();
+        for input in &inputs {
+            let result = list.parse(input);
+            assert!(matches!(result, Ok((_, ""))), "{input} - {result:?}");
+        }
+    }
+
+    #[test]
+    fn empty_choices_emit_nothing() {
+        let parser = pchar('a').then(any_of(&[]));
+        let grammar = Grammar::of(&parser);
+        let mut generator = Generator::new(&grammar, 3);
+        for _ in 0..10 {
+            assert_eq!(generator.generate(), "a");
+        }
+    }
+}
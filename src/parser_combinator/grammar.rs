@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use std::fmt::{self, Write};
+
+use super::parser::Parser;
+
+/// Description of what a parser accepts, as produced by `Parser::describe`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Syntax {
+    Char(char),
+    String(String),
+    Pattern(String),
+    Sequence(Vec<Syntax>),
+    Choice(Vec<Syntax>),
+    Optional(Box<Syntax>),
+    Many(Box<Syntax>),
+    Many1(Box<Syntax>),
+    /// Reference to a named rule of the enclosing `Grammar`.
+    Rule(String),
+    /// A parser that cannot describe itself.
+    Opaque,
+}
+
+impl Syntax {
+    pub fn sequence(items: Vec<Syntax>) -> Syntax {
+        let mut flattened = Vec::new();
+        for item in items {
+            match item {
+                Syntax::Sequence(inner) => flattened.extend(inner),
+                item => flattened.push(item),
+            }
+        }
+        Syntax::Sequence(flattened)
+    }
+
+    pub fn choice(items: Vec<Syntax>) -> Syntax {
+        let mut flattened = Vec::new();
+        for item in items {
+            match item {
+                Syntax::Choice(inner) => flattened.extend(inner),
+                item => flattened.push(item),
+            }
+        }
+        Syntax::Choice(flattened)
+    }
+
+    fn rename(&mut self, from: &str, to: &str) {
+        match self {
+            Syntax::Rule(name) if name == from => *name = to.to_string(),
+            Syntax::Sequence(items) | Syntax::Choice(items) => {
+                items.iter_mut().for_each(|item| item.rename(from, to))
+            }
+            Syntax::Optional(item) | Syntax::Many(item) | Syntax::Many1(item) => {
+                item.rename(from, to)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Formats as EBNF, like the right-hand side of a rule in `Grammar::to_ebnf`.
+impl fmt::Display for Syntax {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&ebnf_syntax(self, 0))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub syntax: Syntax,
+    generated: bool,
+}
+
+/// The named rules reachable from a parser. The first rule is the start rule.
+#[derive(Debug, Default)]
+pub struct Grammar {
+    pub rules: Vec<Rule>,
+    forwards: HashMap<usize, String>,
+}
+
+impl Grammar {
+    pub fn of<'a, P: Parser<'a> + ?Sized>(parser: &P) -> Grammar {
+        let mut grammar = Grammar::default();
+        let syntax = parser.describe(&mut grammar);
+        if !matches!(syntax, Syntax::Rule(_)) {
+            grammar.rules.insert(
+                0,
+                Rule {
+                    name: "start".to_string(),
+                    syntax,
+                    generated: false,
+                },
+            );
+        }
+        grammar.remove_aliases();
+        grammar
+    }
+
+    pub fn rule(&self, name: &str) -> Option<&Syntax> {
+        self.rules
+            .iter()
+            .find(|rule| rule.name == name)
+            .map(|rule| &rule.syntax)
+    }
+
+    /// Describes a rule named `name` unless it is already known, and returns
+    /// a reference to it. Recursive references made while `describe` runs
+    /// resolve to the same rule.
+    pub fn named_rule(
+        &mut self,
+        name: &str,
+        describe: impl FnOnce(&mut Grammar) -> Syntax,
+    ) -> Syntax {
+        if self.rule(name).is_none() {
+            self.define(name.to_string(), false, describe);
+        }
+        Syntax::Rule(name.to_string())
+    }
+
+    /// Like `named_rule`, but for anonymous recursive parsers identified by
+    /// address. They are given generated names such as `rule1`.
+    pub fn forward_rule(
+        &mut self,
+        id: usize,
+        describe: impl FnOnce(&mut Grammar) -> Syntax,
+    ) -> Syntax {
+        if let Some(name) = self.forwards.get(&id) {
+            return Syntax::Rule(name.clone());
+        }
+        let name = format!("rule{}", self.forwards.len() + 1);
+        self.forwards.insert(id, name.clone());
+        self.define(name.clone(), true, describe);
+        Syntax::Rule(name)
+    }
+
+    fn define(
+        &mut self,
+        name: String,
+        generated: bool,
+        describe: impl FnOnce(&mut Grammar) -> Syntax,
+    ) {
+        let index = self.rules.len();
+        self.rules.push(Rule {
+            name,
+            syntax: Syntax::Opaque,
+            generated,
+        });
+        self.rules[index].syntax = describe(self);
+    }
+
+    // A generated rule that only refers to a named rule adds nothing to the
+    // output, so references to it are pointed at the named rule instead.
+    fn remove_aliases(&mut self) {
+        while let Some(index) = self
+            .rules
+            .iter()
+            .position(|rule| rule.generated && matches!(rule.syntax, Syntax::Rule(_)))
+        {
+            let alias = self.rules.remove(index);
+            if let Syntax::Rule(target) = alias.syntax {
+                for rule in self.rules.iter_mut() {
+                    rule.syntax.rename(&alias.name, &target);
+                }
+            }
+        }
+    }
+
+    /// Renders the grammar in W3C-style EBNF, one `name ::= ...` line per rule.
+    /// Patterns are written between slashes, with any `/` in them escaped,
+    /// and a choice with no alternatives as `? never ?`.
+    pub fn to_ebnf(&self) -> String {
+        let mut ebnf = String::new();
+        let width = self
+            .rules
+            .iter()
+            .map(|rule| rule.name.len())
+            .max()
+            .unwrap_or(0);
+        for rule in &self.rules {
+            let _ = writeln!(
+                ebnf,
+                "{:width$} ::= {}",
+                rule.name,
+                ebnf_syntax(&rule.syntax, 0),
+                width = width
+            );
+        }
+        ebnf
+    }
+}
+
+fn ebnf_char(c: char) -> String {
+    match c {
+        '\'' => "\"'\"".to_string(),
+        '\n' => "#xA".to_string(),
+        '\r' => "#xD".to_string(),
+        '\t' => "#x9".to_string(),
+        c => format!("'{}'", c),
+    }
+}
+
+fn ebnf_class(chars: &[char]) -> String {
+    let mut chars = chars.to_vec();
+    chars.sort_unstable();
+    chars.dedup();
+    let mut class = String::from("[");
+    let mut i = 0;
+    while i < chars.len() {
+        let mut j = i;
+        while j + 1 < chars.len() && chars[j + 1] as u32 == chars[j] as u32 + 1 {
+            j += 1;
+        }
+        let escape = |c: char| match c {
+            '\n' => "#xA".to_string(),
+            '\r' => "#xD".to_string(),
+            '\t' => "#x9".to_string(),
+            ']' | '^' | '-' | '\\' => format!("\\{}", c),
+            c => c.to_string(),
+        };
+        if j > i + 1 {
+            let _ = write!(class, "{}-{}", escape(chars[i]), escape(chars[j]));
+        } else {
+            for c in &chars[i..=j] {
+                class.push_str(&escape(*c));
+            }
+        }
+        i = j + 1;
+    }
+    class.push(']');
+    class
+}
+
+// A pattern between slashes, escaping the slashes in it that are not
+// escaped already.
+pub(crate) fn slashed_pattern(pattern: &str) -> String {
+    let mut text = String::from("/");
+    let mut escaped = false;
+    for c in pattern.chars() {
+        if c == '/' && !escaped {
+            text.push('\\');
+        }
+        escaped = c == '\\' && !escaped;
+        text.push(c);
+    }
+    text.push('/');
+    text
+}
+
+// Precedence: 0 = choice, 1 = sequence, 2 = postfix operand.
+fn ebnf_syntax(syntax: &Syntax, precedence: u8) -> String {
+    let parenthesize = |text: String, own: u8| {
+        if own < precedence {
+            format!("( {} )", text)
+        } else {
+            text
+        }
+    };
+    match syntax {
+        Syntax::Char(c) => ebnf_char(*c),
+        Syntax::String(s) if s.contains('\'') => format!("\"{}\"", s),
+        Syntax::String(s) => format!("'{}'", s),
+        Syntax::Pattern(p) => slashed_pattern(p),
+        Syntax::Rule(name) => name.clone(),
+        Syntax::Opaque => "? opaque ?".to_string(),
+        Syntax::Sequence(items) if items.is_empty() => "()".to_string(),
+        Syntax::Sequence(items) => {
+            let items: Vec<_> = items.iter().map(|item| ebnf_syntax(item, 2)).collect();
+            parenthesize(items.join(" "), 1)
+        }
+        Syntax::Choice(items) if items.is_empty() => "? never ?".to_string(),
+        Syntax::Choice(items) => {
+            let chars: Option<Vec<char>> = items
+                .iter()
+                .map(|item| match item {
+                    Syntax::Char(c) => Some(*c),
+                    _ => None,
+                })
+                .collect();
+            match chars {
+                Some(chars) if chars.len() > 1 => ebnf_class(&chars),
+                _ => {
+                    let items: Vec<_> = items.iter().map(|item| ebnf_syntax(item, 1)).collect();
+                    parenthesize(items.join(" | "), 0)
+                }
+            }
+        }
+        Syntax::Optional(item) => format!("{}?", ebnf_syntax(item, 2)),
+        Syntax::Many(item) => format!("{}*", ebnf_syntax(item, 2)),
+        Syntax::Many1(item) => format!("{}+", ebnf_syntax(item, 2)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser_combinator::parser::*;
+
+    #[test]
+    fn describe_combinators() {
+        let number = pchar('-').optional().then(any_of(&['0', '1', '2']).many1());
+        let grammar = Grammar::of(&number);
+
+        assert_eq!(grammar.to_ebnf(), "start ::= '-'? [0-2]+\n");
+    }
+
+    #[test]
+    fn describe_named_rules() {
+        let digit = any_of(&['0', '1']).named("digit");
+        let pair = digit.clone().then(pstring(", ").right(digit)).named("pair");
+        let grammar = Grammar::of(&pair);
+
+        assert_eq!(
+            grammar.to_ebnf(),
+            "pair  ::= digit ', ' digit\ndigit ::= [01]\n"
+        );
+    }
+
+    #[test]
+    fn describe_recursive_rules() {
+        let mut nested = forward();
+        let inner: RcParser<char> = nested.clone();
+        let parens = inner.between(pchar('('), pchar(')'));
+        set_implementation(&mut nested, parens.or(pchar('x')).named("nested"));
+        let grammar = Grammar::of(nested.as_ref());
+
+        assert_eq!(grammar.to_ebnf(), "nested ::= '(' nested ')' | 'x'\n");
+    }
+}
@@ -0,0 +1,320 @@
+// A JSON (RFC 8259) parser built from the crate's combinators.
+//
+// Every value records the byte span it was parsed from. Object members keep
+// their source order, and duplicate keys are kept rather than merged.
+// Numbers are read as `f64`, so integers beyond 2^53 lose precision and
+// out-of-range exponents become infinities, as in JavaScript.
+
+use std::ops::Range;
+use std::rc::Rc;
+
+use crate::parser_combinator::error::{ErrorKind, Expected, ParseError};
+use crate::parser_combinator::grammar::{Grammar, Syntax};
+use crate::parser_combinator::parser::*;
+use crate::parser_combinator::session::ParseSession;
+
+/// How deeply `parse` lets arrays and objects nest.
+pub const MAX_DEPTH: usize = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Json {
+    pub value: JsonValue,
+    /// Byte range of the value in the source, without surrounding
+    /// whitespace.
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// The member of an object with the given key. If the key is repeated,
+    /// the last one wins.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match &self.value {
+            JsonValue::Object(members) => {
+                members.iter().rev().find(|(k, _)| k == key).map(|(_, v)| v)
+            }
+            _ => None,
+        }
+    }
+}
+
+struct SpannedParser<'a> {
+    parser: RcParser<'a, JsonValue>,
+}
+
+impl<'a> Parser<'a> for SpannedParser<'a> {
+    type Output = Json;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let (value, remaining) = self.parser.parse_in(input, session)?;
+        let span = session.offset(input)..session.offset(remaining);
+        Ok((Json { value, span }, remaining))
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.parser.describe(grammar)
+    }
+}
+
+struct StringParser;
+
+// Reads the four hex digits of a `\u` escape.
+fn hex4(input: &str) -> Option<u32> {
+    let digits = input.get(..4)?;
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u32::from_str_radix(digits, 16).ok()
+}
+
+impl StringParser {
+    // Parses a `\u` escape, pairing surrogates, and returns the input after it.
+    fn unicode(escape: &str) -> Result<(char, &str), ParseError> {
+        let invalid = || ParseError::new("Invalid unicode escape", escape);
+        let high = hex4(&escape[2..]).ok_or_else(invalid)?;
+        let rest = &escape[6..];
+        if !(0xD800..0xE000).contains(&high) {
+            return Ok((char::from_u32(high).unwrap(), rest));
+        }
+        let low = rest
+            .strip_prefix("\\u")
+            .and_then(hex4)
+            .filter(|low| high < 0xDC00 && (0xDC00..0xE000).contains(low));
+        match low {
+            Some(low) => {
+                let code = 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00);
+                Ok((char::from_u32(code).unwrap(), &rest[6..]))
+            }
+            None => Err(ParseError::new(
+                format!("Unpaired surrogate {}", &escape[..6]),
+                escape,
+            )),
+        }
+    }
+}
+
+impl<'a> Parser<'a> for StringParser {
+    type Output = String;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        session.step(input)?;
+        let Some(mut remaining) = input.strip_prefix('"') else {
+            return Err(ParseError::new("Expected '\"'", input).with_expected(Expected::Char('"')));
+        };
+        let mut value = String::new();
+        loop {
+            let mut chars = remaining.chars();
+            match chars.next() {
+                None => return Err(ParseError::new("Unterminated string", input)),
+                Some('"') => {
+                    let remaining = chars.as_str();
+                    session.token("text", input, remaining);
+                    return Ok((value, remaining));
+                }
+                Some('\\') => {
+                    let c = match chars.next() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('/') => '/',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('n') => '\n',
+                        Some('r') => '\r',
+                        Some('t') => '\t',
+                        Some('u') => {
+                            let (c, rest) = Self::unicode(remaining)?;
+                            value.push(c);
+                            remaining = rest;
+                            continue;
+                        }
+                        _ => {
+                            let sequence: String = remaining.chars().take(2).collect();
+                            return Err(ParseError::new(
+                                format!("Invalid escape sequence {}", sequence),
+                                remaining,
+                            ));
+                        }
+                    };
+                    value.push(c);
+                    remaining = chars.as_str();
+                }
+                Some(c) if c < ' ' => {
+                    return Err(ParseError::new(
+                        format!("Unescaped control character {:?} in string", c),
+                        remaining,
+                    ))
+                }
+                Some(c) => {
+                    value.push(c);
+                    remaining = chars.as_str();
+                }
+            }
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, _grammar: &mut Grammar) -> Syntax {
+        Syntax::Pattern(r#""([^"\\]|\\["\\/bfnrtu])*""#.to_string())
+    }
+}
+
+fn string<'a>() -> RcParser<'a, String> {
+    StringParser.to_rc().token("string").named("string")
+}
+
+fn number<'a>() -> RcParser<'a, JsonValue> {
+    pattern(r"-?(0|[1-9][0-9]*)(\.[0-9]+)?([eE][+-]?[0-9]+)?")
+        .map(|text| JsonValue::Number(text.parse().unwrap()))
+        .token("number")
+        .named("number")
+}
+
+fn literal<'a>() -> RcParser<'a, JsonValue> {
+    choice(vec![
+        pstring("null").map(|_| JsonValue::Null),
+        pstring("true").map(|_| JsonValue::Bool(true)),
+        pstring("false").map(|_| JsonValue::Bool(false)),
+    ])
+    .token("literal")
+}
+
+/// A JSON value, including any whitespace after it.
+pub fn json<'a>() -> RcParser<'a, Json> {
+    let mut value = forward();
+    let element: RcParser<Json> = value.clone();
+    let element = element.ws();
+
+    let array = element
+        .sep_by(pchar(',').ws())
+        .between(pchar('[').ws(), pchar(']'))
+        .map(JsonValue::Array)
+        .node("array")
+        .named("array");
+    let member = string()
+        .ws()
+        .left(pchar(':').ws())
+        .then(element.clone())
+        .node("member")
+        .named("member");
+    let object = member
+        .sep_by(pchar(',').ws())
+        .between(pchar('{').ws(), pchar('}'))
+        .map(JsonValue::Object)
+        .node("object")
+        .named("object");
+
+    let implementation = SpannedParser {
+        parser: choice(vec![
+            object,
+            array,
+            string().map(JsonValue::String),
+            number(),
+            literal(),
+        ]),
+    }
+    .to_rc()
+    .named("value");
+    set_implementation(&mut value, implementation);
+    element
+}
+
+/// Parses a complete JSON document, which may be surrounded by whitespace.
+/// Arrays and objects may nest up to `MAX_DEPTH` deep.
+pub fn parse(source: &str) -> Result<Json, ParseError> {
+    let document = pattern(r"[ \t\n\r]*").token("whitespace").right(json());
+    let session = ParseSession::new().max_depth(MAX_DEPTH).grow_stack();
+    match session.parse(&document, source) {
+        Ok((json, "")) => Ok(json),
+        Ok((_, remaining)) => Err(ParseError::new("Expected end of input", remaining)),
+        // Every level adds its own prefix to the message, so report the
+        // limit once instead.
+        Err(error) if error.kind == ErrorKind::TooDeep => Err(ParseError::too_deep(
+            format!("Arrays and objects are nested more than {} deep", MAX_DEPTH),
+            &source[error.offset(source)..],
+        )),
+        Err(error) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_and_spans() {
+        let source = " {\"a\": [1, -2.5e1, true], \"b\\u00e9\": null, \"a\": \"x\"} ";
+        let json = parse(source).unwrap();
+
+        assert_eq!(json.span, 1..source.len() - 1);
+        let array = json.get("a").unwrap();
+        assert_eq!(array.value, JsonValue::String("x".to_string()));
+        let JsonValue::Object(members) = &json.value else {
+            panic!("expected an object");
+        };
+        assert_eq!(members[1].0, "bé");
+        let JsonValue::Array(items) = &members[0].1.value else {
+            panic!("expected an array");
+        };
+        let values: Vec<_> = items.iter().map(|item| item.value.clone()).collect();
+        assert_eq!(
+            values,
+            vec![
+                JsonValue::Number(1.0),
+                JsonValue::Number(-25.0),
+                JsonValue::Bool(true)
+            ]
+        );
+        assert_eq!(&source[items[1].span.clone()], "-2.5e1");
+    }
+
+    #[test]
+    fn strings_decode_escapes_and_surrogate_pairs() {
+        let json = parse(r#""\"\\\/\b\f\n\r\t😀""#).unwrap();
+        assert_eq!(
+            json.value,
+            JsonValue::String("\"\\/\u{8}\u{c}\n\r\t\u{1F600}".to_string())
+        );
+
+        let error = parse(r#""\ud800x""#).unwrap_err();
+        assert!(error.message.ends_with("Unpaired surrogate \\ud800"));
+    }
+
+    #[test]
+    fn errors_point_at_the_problem() {
+        let source = "{\"a\": [1, 2,]}";
+        let error = parse(source).unwrap_err();
+        assert_eq!(error.offset(source), 12);
+        assert_eq!(error.expected, vec![Expected::Rule("value")]);
+
+        let error = parse("[1] [2]").unwrap_err();
+        assert_eq!(error.message, "Expected end of input");
+        assert_eq!(error.remaining, 3);
+    }
+
+    #[test]
+    fn deep_nesting() {
+        let nested = |depth: usize| format!("{}{}", "[".repeat(depth), "]".repeat(depth));
+        assert!(parse(&nested(500)).is_ok());
+        assert!(parse(&format!("{}1{}", "{\"a\":".repeat(500), "}".repeat(500))).is_ok());
+
+        let source = "[".repeat(100_000);
+        let error = parse(&source).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TooDeep);
+        assert_eq!(error.message, "Arrays and objects are nested more than 512 deep");
+        assert_eq!(error.offset(&source), MAX_DEPTH);
+    }
+}
@@ -0,0 +1,3 @@
+// Parsers for complete formats, built only from the crate's combinators.
+
+pub mod json;
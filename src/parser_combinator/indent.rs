@@ -0,0 +1,327 @@
+// Combinators for offside-rule (indentation-sensitive) formats.
+//
+// The session keeps a stack of block indentations. `indented_block` opens a
+// block at the column of its first item, `aligned` checks an item starts a
+// line at the current block's indentation, and `same_line` and
+// `continues_line` keep an item on the line it continues. Columns are
+// counted in characters from 0, so a tab counts as one column.
+
+use std::rc::Rc;
+
+use super::error::ParseError;
+use super::grammar::{Grammar, Syntax};
+use super::parser::{ParseResult, Parser, RcParser};
+use super::session::ParseSession;
+
+fn skip_blank(input: &str) -> &str {
+    input.trim_start_matches([' ', '\t', '\r', '\n'])
+}
+
+fn skip_blank_back(input: &str) -> &str {
+    input.trim_end_matches([' ', '\t', '\r', '\n'])
+}
+
+fn skip_spaces(input: &str) -> &str {
+    input.trim_start_matches([' ', '\t'])
+}
+
+// The indentation of the line `input` is on, if only whitespace precedes it.
+fn line_indentation(input: &str, session: &ParseSession) -> Option<usize> {
+    let offset = session.offset(input);
+    let source = session.source();
+    let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let prefix = &source[line_start..offset];
+    prefix
+        .chars()
+        .all(|c| c == ' ' || c == '\t' || c == '\r')
+        .then(|| prefix.chars().count())
+}
+
+struct IndentedBlockParser<'a, Output> {
+    item: RcParser<'a, Output>,
+}
+
+impl<'a, Output: 'a> Parser<'a> for IndentedBlockParser<'a, Output> {
+    type Output = Vec<Output>;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let parent = session.indentation();
+        let first = skip_blank(input);
+        if first.is_empty() {
+            return Err(ParseError::new("Expected an indented block", first));
+        }
+        let column = match line_indentation(first, session) {
+            Some(column) if column > parent => column,
+            Some(column) => {
+                return Err(ParseError::new(
+                    format!(
+                        "Expected indentation greater than {}, found {}",
+                        parent, column
+                    ),
+                    first,
+                ))
+            }
+            None => {
+                return Err(ParseError::new(
+                    "Expected an indented block on a new line",
+                    first,
+                ))
+            }
+        };
+
+        session.push_indentation(column);
+        let result = self.items(input, column, parent, session);
+        session.pop_indentation();
+        result
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        Syntax::Many1(Box::new(self.item.describe(grammar)))
+    }
+}
+
+impl<'a, Output: 'a> IndentedBlockParser<'a, Output> {
+    fn items(
+        &self,
+        input: &'a str,
+        column: usize,
+        parent: usize,
+        session: &ParseSession,
+    ) -> ParseResult<'a, Vec<Output>> {
+        let mut items = Vec::new();
+        let mut remaining = input;
+        loop {
+            let next = skip_blank(remaining);
+            if next.is_empty() {
+                break;
+            }
+            match line_indentation(next, session) {
+                // The rest of an item's line is left to the enclosing parser.
+                None if !items.is_empty() => break,
+                Some(found) if found < column && found <= parent => break,
+                Some(found) if found != column => {
+                    return Err(ParseError::new(
+                        format!("Expected indentation of {}, found {}", column, found),
+                        next,
+                    ))
+                }
+                _ => {}
+            }
+            session.token("whitespace", remaining, next);
+            let (item, rest) = self.item.parse_in(next, session)?;
+            items.push(item);
+            remaining = rest;
+        }
+        Ok((items, remaining))
+    }
+}
+
+/// One or more `item`s, each starting a line at the same column, indented
+/// further than the enclosing block. The block ends at the first line
+/// indented back to (or past) the enclosing block's level.
+pub fn indented_block<'a, Output: 'a>(item: RcParser<'a, Output>) -> RcParser<'a, Vec<Output>> {
+    IndentedBlockParser { item }.to_rc()
+}
+
+struct AlignedParser<'a, Output> {
+    parser: RcParser<'a, Output>,
+}
+
+impl<'a, Output: 'a> Parser<'a> for AlignedParser<'a, Output> {
+    type Output = Output;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let next = skip_blank(input);
+        let expected = session.indentation();
+        match line_indentation(next, session) {
+            Some(found) if found == expected => {
+                session.token("whitespace", input, next);
+                self.parser.parse_in(next, session)
+            }
+            Some(found) => Err(ParseError::new(
+                format!("Expected indentation of {}, found {}", expected, found),
+                next,
+            )),
+            None => Err(ParseError::new("Expected item to start a new line", next)),
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.parser.describe(grammar)
+    }
+}
+
+/// Skips blank lines, then runs `parser` if it starts a line at the current
+/// block's indentation.
+pub fn aligned<'a, Output: 'a>(parser: RcParser<'a, Output>) -> RcParser<'a, Output> {
+    AlignedParser { parser }.to_rc()
+}
+
+struct SameLineParser<'a, Output> {
+    parser: RcParser<'a, Output>,
+}
+
+impl<'a, Output: 'a> Parser<'a> for SameLineParser<'a, Output> {
+    type Output = Output;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let next = skip_spaces(input);
+        if next.starts_with(['\n', '\r']) {
+            return Err(ParseError::new("Expected item on the same line", next));
+        }
+        session.token("whitespace", input, next);
+        self.parser.parse_in(next, session)
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.parser.describe(grammar)
+    }
+}
+
+/// Skips spaces and tabs, then runs `parser`, failing if it would have to
+/// continue on the next line.
+pub fn same_line<'a, Output: 'a>(parser: RcParser<'a, Output>) -> RcParser<'a, Output> {
+    SameLineParser { parser }.to_rc()
+}
+
+struct ContinuesLineParser<'a, Output> {
+    parser: RcParser<'a, Output>,
+}
+
+impl<'a, Output: 'a> Parser<'a> for ContinuesLineParser<'a, Output> {
+    type Output = Output;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let next = skip_blank(input);
+        // Whitespace before `input` may already have been consumed, so look
+        // back from `next` rather than at what is skipped here.
+        let before = &session.source()[..session.offset(next)];
+        let gap = &before[skip_blank_back(before).len()..];
+        if gap.contains('\n') {
+            return Err(ParseError::new("Expected item on the same line", next));
+        }
+        session.token("whitespace", input, next);
+        self.parser.parse_in(next, session)
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.parser.describe(grammar)
+    }
+}
+
+/// Skips whitespace, then runs `parser` if no line break separates it from
+/// the text before it, such as a binary operator continuing an expression.
+pub fn continues_line<'a, Output: 'a>(parser: RcParser<'a, Output>) -> RcParser<'a, Output> {
+    ContinuesLineParser { parser }.to_rc()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser_combinator::parser::*;
+
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Scalar(String),
+        Map(Vec<(String, Value)>),
+    }
+
+    fn entry<'a>() -> RcParser<'a, (String, Value)> {
+        let mut entry = forward();
+        let key = pattern("[a-z]+")
+            .map(|key| key.to_string())
+            .left(pchar(':'));
+        let scalar = same_line(pattern("[a-z0-9]+")).map(|value| Value::Scalar(value.to_string()));
+        let inner: RcParser<(String, Value)> = entry.clone();
+        let map = indented_block(inner).map(Value::Map);
+        set_implementation(&mut entry, key.then(scalar.or(map)));
+        entry
+    }
+
+    fn document<'a>() -> RcParser<'a, Vec<(String, Value)>> {
+        aligned(entry()).many1()
+    }
+
+    fn scalar(key: &str, value: &str) -> (String, Value) {
+        (key.to_string(), Value::Scalar(value.to_string()))
+    }
+
+    #[test]
+    fn nested_blocks() {
+        let source = "server:\n  host: local\n  limits:\n    cpu: 2\n\n  port: 80\nname: x\n";
+        let (document, remaining) = document().parse(source).unwrap();
+
+        let server = Value::Map(vec![
+            scalar("host", "local"),
+            ("limits".to_string(), Value::Map(vec![scalar("cpu", "2")])),
+            scalar("port", "80"),
+        ]);
+        assert_eq!(
+            document,
+            vec![("server".to_string(), server), scalar("name", "x")]
+        );
+        assert_eq!(remaining, "\n");
+    }
+
+    fn section<'a>() -> RcParser<'a, (&'a str, Vec<&'a str>)> {
+        let item = pattern("[a-z]+: [0-9]+");
+        pattern("[a-z]+")
+            .left(pchar(':'))
+            .then(indented_block(item))
+    }
+
+    #[test]
+    fn inconsistent_dedent_is_an_error() {
+        let source = "a:\n    b: 1\n  c: 2\n";
+        let error = section().parse(source).unwrap_err();
+
+        assert!(error
+            .message
+            .ends_with("Expected indentation of 4, found 2"));
+        assert_eq!(error.line_col(source), (3, 3));
+    }
+
+    #[test]
+    fn block_must_be_indented() {
+        let error = section().parse("a:\nb: 1").unwrap_err();
+
+        assert!(error
+            .message
+            .ends_with("Expected indentation greater than 0, found 0"));
+    }
+
+    #[test]
+    fn same_line_rejects_a_line_break() {
+        let value = pattern("[a-z]+")
+            .left(pchar(':'))
+            .then(same_line(pattern("[a-z]+")));
+
+        let error = value.parse("a:\nb").unwrap_err();
+        assert!(error.message.ends_with("Expected item on the same line"));
+    }
+
+    #[test]
+    fn continues_line_rejects_an_earlier_line_break() {
+        let sum = pattern("[0-9]+").ws().then(
+            continues_line(pchar('+'))
+                .ws()
+                .right(pattern("[0-9]+"))
+                .many(),
+        );
+
+        assert_eq!(sum.parse("1 + 2 +\n3"), Ok((("1", vec!["2", "3"]), "")));
+        assert_eq!(sum.parse("1 + 2\n+ 3"), Ok((("1", vec!["2"]), "\n+ 3")));
+    }
+}
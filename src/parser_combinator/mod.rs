@@ -1,5 +1,24 @@
+pub mod binary;
+pub mod cst;
+pub mod error;
+pub mod generate;
+pub mod grammar;
+pub mod grammars;
+pub mod indent;
+pub mod numeric;
 pub mod parser;
+pub mod permutation;
+pub mod railroad;
+pub mod regex;
+pub mod rng;
+pub mod session;
+pub mod state;
+pub mod string;
 
+pub use error::{ErrorKind, Expected, ParseError};
+pub use grammar::{Grammar, Syntax};
 pub use parser::Parser;
 pub use parser::RcParser;
-pub use parser::{any_of, pchar};
+pub use parser::{any_of, pattern, pchar};
+pub use permutation::permutation;
+pub use session::ParseSession;
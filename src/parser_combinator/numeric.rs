@@ -0,0 +1,186 @@
+// Numeric literal parsers.
+//
+// Every literal may start with `-` and may separate digits with single
+// underscores, as in `1_000_000`. Literals that match but do not fit the
+// target type fail the whole parse, with an `Invalid` error positioned at the
+// start of the literal, rather than letting another parser read part of it.
+
+use std::num::{IntErrorKind, ParseIntError};
+use std::rc::Rc;
+
+use super::error::ParseError;
+use super::grammar::{Grammar, Syntax};
+use super::parser::{pattern, ParseResult, Parser, RcParser};
+use super::session::ParseSession;
+
+/// Integer types that literals can be parsed into.
+pub trait Integer: Sized + 'static {
+    const NAME: &'static str;
+    fn from_str_radix(digits: &str, radix: u32) -> Result<Self, ParseIntError>;
+}
+
+macro_rules! integer_impl {
+    ($($t:ty)*) => {
+        $(
+            impl Integer for $t {
+                const NAME: &'static str = stringify!($t);
+                fn from_str_radix(digits: &str, radix: u32) -> Result<Self, ParseIntError> {
+                    <$t>::from_str_radix(digits, radix)
+                }
+            }
+        )*
+    };
+}
+
+integer_impl!(i8 i16 i32 i64 i128 isize u8 u16 u32 u64 u128 usize);
+
+struct LiteralParser<'a, T> {
+    text: RcParser<'a, &'a str>,
+    convert: fn(&str) -> Result<T, String>,
+}
+
+impl<'a, T: 'a> Parser<'a> for LiteralParser<'a, T> {
+    type Output = T;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let (text, remaining) = self.text.parse_in(input, session)?;
+        match (self.convert)(text) {
+            Ok(value) => Ok((value, remaining)),
+            Err(message) => Err(ParseError::invalid(message, input)),
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.text.describe(grammar)
+    }
+}
+
+fn literal<'a, T: 'a>(regex: &str, convert: fn(&str) -> Result<T, String>) -> RcParser<'a, T> {
+    LiteralParser {
+        text: pattern(regex),
+        convert,
+    }
+    .to_rc()
+}
+
+fn convert_integer<T: Integer>(text: &str, prefix_len: usize, radix: u32) -> Result<T, String> {
+    let (sign, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => ("-", unsigned),
+        None => ("", text),
+    };
+    let digits: String = unsigned[prefix_len..]
+        .chars()
+        .filter(|&c| c != '_')
+        .collect();
+    T::from_str_radix(&format!("{}{}", sign, digits), radix).map_err(|error| match error.kind() {
+        IntErrorKind::PosOverflow | IntErrorKind::NegOverflow | IntErrorKind::InvalidDigit => {
+            format!("Integer literal {} does not fit in {}", text, T::NAME)
+        }
+        _ => format!("Invalid integer literal {}", text),
+    })
+}
+
+/// A decimal integer such as `42`, `-7` or `1_000`.
+pub fn integer<'a, T: Integer>() -> RcParser<'a, T> {
+    literal("-?[0-9](_?[0-9])*", |text| convert_integer(text, 0, 10))
+}
+
+/// A hexadecimal integer such as `0xFF`.
+pub fn hex<'a, T: Integer>() -> RcParser<'a, T> {
+    literal("-?0[xX][0-9a-fA-F](_?[0-9a-fA-F])*", |text| {
+        convert_integer(text, 2, 16)
+    })
+}
+
+/// An octal integer such as `0o755`.
+pub fn oct<'a, T: Integer>() -> RcParser<'a, T> {
+    literal("-?0[oO][0-7](_?[0-7])*", |text| convert_integer(text, 2, 8))
+}
+
+/// A binary integer such as `0b1010`.
+pub fn bin<'a, T: Integer>() -> RcParser<'a, T> {
+    literal("-?0[bB][01](_?[01])*", |text| convert_integer(text, 2, 2))
+}
+
+/// A decimal number with an optional fraction and exponent, such as `3`,
+/// `-0.5` or `6.02e23`.
+pub fn float<'a>() -> RcParser<'a, f64> {
+    literal(
+        "-?[0-9](_?[0-9])*(\\.[0-9](_?[0-9])*)?([eE][+-]?[0-9](_?[0-9])*)?",
+        convert_float,
+    )
+}
+
+/// Like `float`, but the number must have a fraction or an exponent, as in
+/// `0.5` or `1e-3`, so that it can be told apart from an integer.
+pub fn fractional<'a>() -> RcParser<'a, f64> {
+    literal(
+        "-?[0-9](_?[0-9])*(\\.[0-9](_?[0-9])*([eE][+-]?[0-9](_?[0-9])*)?|[eE][+-]?[0-9](_?[0-9])*)",
+        convert_float,
+    )
+}
+
+fn convert_float(text: &str) -> Result<f64, String> {
+    let digits: String = text.chars().filter(|&c| c != '_').collect();
+    match digits.parse::<f64>() {
+        Ok(value) if value.is_finite() => Ok(value),
+        _ => Err(format!("Float literal {} is out of range", text)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser_combinator::ErrorKind;
+
+    #[test]
+    fn integer_literals() {
+        assert_eq!(integer::<i32>().parse("1_000 "), Ok((1000, " ")));
+        assert_eq!(integer::<i64>().parse("-42"), Ok((-42, "")));
+        assert_eq!(hex::<u32>().parse("0xff_FF"), Ok((0xffff, "")));
+        assert_eq!(oct::<i32>().parse("-0o17"), Ok((-15, "")));
+        assert_eq!(bin::<u8>().parse("0b1010_1010"), Ok((0b1010_1010, "")));
+        assert_eq!(integer::<u8>().parse("12_"), Ok((12, "_")));
+    }
+
+    #[test]
+    fn integer_overflow_is_a_positioned_error() {
+        let source = "x = 300";
+        let assign = pattern("x = ").right(integer::<u8>());
+
+        let error = assign.parse(source).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Invalid);
+        assert!(error
+            .message
+            .ends_with("Integer literal 300 does not fit in u8"));
+        assert_eq!(error.offset(source), 4);
+
+        // A smaller number that the literal starts with is not tried.
+        let number = hex::<u8>().or(integer::<u8>());
+        let error = number.parse("0x100").unwrap_err();
+        assert_eq!(error.message, "Integer literal 0x100 does not fit in u8");
+
+        let error = integer::<u32>().parse("-1").unwrap_err();
+        assert_eq!(error.message, "Integer literal -1 does not fit in u32");
+    }
+
+    #[test]
+    fn float_literals() {
+        assert_eq!(float().parse("3"), Ok((3.0, "")));
+        assert_eq!(float().parse("-0.25"), Ok((-0.25, "")));
+        assert_eq!(float().parse("6.02e23"), Ok((6.02e23, "")));
+        assert_eq!(float().parse("1_000.5E-1"), Ok((100.05, "")));
+        assert_eq!(float().parse("1."), Ok((1.0, ".")));
+
+        let error = float().parse("1e999").unwrap_err();
+        assert_eq!(error.message, "Float literal 1e999 is out of range");
+
+        assert_eq!(fractional().parse("2.5e-1"), Ok((0.25, "")));
+        assert_eq!(fractional().parse("1E3"), Ok((1000.0, "")));
+        assert!(fractional().parse("3").is_err());
+        assert!(fractional().parse("1.").is_err());
+    }
+}
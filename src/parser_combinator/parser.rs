@@ -1,13 +1,42 @@
 use std::rc::Rc;
 
+use super::cst::Event;
+use super::error::{Expected, ParseError};
+use super::grammar::{Grammar, Syntax};
+use super::regex::Regex;
+use super::session::ParseSession;
+
+pub type ParseResult<'a, Output> = Result<(Output, &'a str), ParseError>;
+
 pub trait Parser<'a> {
     type Output;
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String>;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output>;
+    fn parse(&self, input: &'a str) -> ParseResult<'a, Self::Output> {
+        ParseSession::new().parse(self, input)
+    }
+
+    /// Parses `input` with `state` available to the `state` combinators.
+    fn parse_with_state<S: 'static>(
+        &self,
+        input: &'a str,
+        state: &mut S,
+    ) -> ParseResult<'a, Self::Output>
+    where
+        Self: Sized,
+    {
+        ParseSession::new().parse_with_state(self, input, state)
+    }
     fn to_rc(self) -> RcParser<'a, Self::Output>;
 
-    fn map<F: 'a, Out: 'a>(&self, f: F) -> RcParser<'a, Out>
+    /// Describes the input this parser accepts. Rules reached along the way
+    /// are added to `grammar`; see `Grammar::of`.
+    fn describe(&self, _grammar: &mut Grammar) -> Syntax {
+        Syntax::Opaque
+    }
+
+    fn map<F, Out: 'a>(&self, f: F) -> RcParser<'a, Out>
     where
-        F: Fn(Self::Output) -> Out,
+        F: Fn(Self::Output) -> Out + 'a,
         Self: Sized + 'a + Clone,
     {
         MapParser {
@@ -66,6 +95,37 @@ pub trait Parser<'a> {
         .to_rc()
     }
 
+    /// One or more terms separated by `op`, whose output combines the
+    /// terms either side of it. Combines from the left, so `1 - 2 - 3`
+    /// is `(1 - 2) - 3`.
+    fn chainl1<F>(&self, op: RcParser<'a, F>) -> RcParser<'a, Self::Output>
+    where
+        F: Fn(Self::Output, Self::Output) -> Self::Output + 'a,
+        Self: Sized + 'a + Clone,
+    {
+        ChainParser {
+            term: self.clone().to_rc(),
+            op,
+            right: false,
+        }
+        .to_rc()
+    }
+
+    /// Like `chainl1`, but combines from the right, so `2 ^ 3 ^ 2` is
+    /// `2 ^ (3 ^ 2)`.
+    fn chainr1<F>(&self, op: RcParser<'a, F>) -> RcParser<'a, Self::Output>
+    where
+        F: Fn(Self::Output, Self::Output) -> Self::Output + 'a,
+        Self: Sized + 'a + Clone,
+    {
+        ChainParser {
+            term: self.clone().to_rc(),
+            op,
+            right: true,
+        }
+        .to_rc()
+    }
+
     fn left<U: 'a>(&self, other: RcParser<'a, U>) -> RcParser<'a, Self::Output>
     where
         Self: Sized + 'a + Clone,
@@ -91,11 +151,35 @@ pub trait Parser<'a> {
         left.right(self.left(right))
     }
 
+    /// Zero or more items separated by `separator`, such as `a, b, c`.
+    fn sep_by<U: 'a>(&self, separator: RcParser<'a, U>) -> RcParser<'a, Vec<Self::Output>>
+    where
+        Self: Sized + 'a + Clone,
+        Self::Output: 'a,
+    {
+        self.sep_by1(separator)
+            .optional()
+            .map(Option::unwrap_or_default)
+    }
+
+    /// Like `sep_by`, but requires at least one item.
+    fn sep_by1<U: 'a>(&self, separator: RcParser<'a, U>) -> RcParser<'a, Vec<Self::Output>>
+    where
+        Self: Sized + 'a + Clone,
+        Self::Output: 'a,
+    {
+        let rest = separator.right(self.clone().to_rc()).many();
+        self.then(rest).map(|(first, mut rest)| {
+            rest.insert(0, first);
+            rest
+        })
+    }
+
     fn ws(&self) -> RcParser<'a, Self::Output>
     where
         Self: Sized + 'a + Clone,
     {
-        let ws = any_of(&[' ', '\t', '\n', '\r']).many();
+        let ws = pattern(r"[ \t\n\r]*").named("ws").token("whitespace");
         self.left(ws)
     }
 
@@ -103,9 +187,47 @@ pub trait Parser<'a> {
     where
         Self: Sized + 'a + Clone,
     {
-        let ws = any_of(&[' ', '\t', '\n', '\r']).many1();
+        let ws = pattern(r"[ \t\n\r]+").named("ws1").token("whitespace");
         self.left(ws)
     }
+
+    /// Records everything the parser consumes as a single `kind` token in
+    /// the concrete syntax tree.
+    fn token(&self, kind: &'static str) -> RcParser<'a, Self::Output>
+    where
+        Self: Sized + 'a + Clone,
+    {
+        TokenParser {
+            kind,
+            parser: self.clone().to_rc(),
+        }
+        .to_rc()
+    }
+
+    /// Groups the tokens the parser consumes under a `kind` node in the
+    /// concrete syntax tree.
+    fn node(&self, kind: &'static str) -> RcParser<'a, Self::Output>
+    where
+        Self: Sized + 'a + Clone,
+    {
+        NodeParser {
+            kind,
+            parser: self.clone().to_rc(),
+        }
+        .to_rc()
+    }
+
+    /// Gives the parser a rule name in its grammar description.
+    fn named(&self, name: &'static str) -> RcParser<'a, Self::Output>
+    where
+        Self: Sized + 'a + Clone,
+    {
+        NamedParser {
+            name,
+            parser: self.clone().to_rc(),
+        }
+        .to_rc()
+    }
 }
 
 pub type RcParser<'a, R> = Rc<dyn Parser<'a, Output = R> + 'a>;
@@ -113,14 +235,18 @@ pub type RcParser<'a, R> = Rc<dyn Parser<'a, Output = R> + 'a>;
 impl<'a, R> Parser<'a> for RcParser<'a, R> {
     type Output = R;
 
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
         let parser = self.as_ref();
-        parser.parse(input)
+        parser.parse_in(input, session)
     }
 
     fn to_rc(self) -> RcParser<'a, R> {
         self
     }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.as_ref().describe(grammar)
+    }
 }
 
 struct CharParser {
@@ -129,18 +255,27 @@ struct CharParser {
 
 impl<'a> Parser<'a> for CharParser {
     type Output = char;
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        session.step(input)?;
         if input.is_empty() {
-            Result::Err(format!("Empty String - expected {}", self.c))
+            Result::Err(
+                ParseError::new(format!("Empty String - expected {}", self.c), input)
+                    .with_expected(Expected::Char(self.c)),
+            )
         } else {
             let head = input.chars().next().unwrap();
             if head == self.c {
-                Result::Ok((head, &input[1..]))
+                let remaining = &input[head.len_utf8()..];
+                session.token("text", input, remaining);
+                Result::Ok((head, remaining))
             } else {
-                Result::Err(format!(
-                    "Expected {}, got {}. Remaining {}",
-                    self.c, head, input
-                ))
+                Result::Err(
+                    ParseError::new(
+                        format!("Expected {}, got {}. Remaining {}", self.c, head, input),
+                        input,
+                    )
+                    .with_expected(Expected::Char(self.c)),
+                )
             }
         }
     }
@@ -148,6 +283,10 @@ impl<'a> Parser<'a> for CharParser {
     fn to_rc(self) -> RcParser<'a, Self::Output> {
         Rc::new(self)
     }
+
+    fn describe(&self, _grammar: &mut Grammar) -> Syntax {
+        Syntax::Char(self.c)
+    }
 }
 
 struct StringParser {
@@ -156,16 +295,51 @@ struct StringParser {
 
 impl<'a> Parser<'a> for StringParser {
     type Output = &'static str;
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        session.step(input)?;
         if let Some(value) = input.strip_prefix(self.string) {
+            session.token("text", input, value);
             Result::Ok((self.string, value))
         } else {
-            Result::Err(format!("Expected {}", self.string))
+            Result::Err(
+                ParseError::new(format!("Expected {}", self.string), input)
+                    .with_expected(Expected::String(self.string)),
+            )
         }
     }
     fn to_rc(self) -> RcParser<'a, Self::Output> {
         Rc::new(self)
     }
+    fn describe(&self, _grammar: &mut Grammar) -> Syntax {
+        Syntax::String(self.string.to_string())
+    }
+}
+
+struct PatternParser {
+    regex: Regex,
+}
+
+impl<'a> Parser<'a> for PatternParser {
+    type Output = &'a str;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        session.step(input)?;
+        match self.regex.match_len(input) {
+            Some(len) => {
+                session.token("text", input, &input[len..]);
+                Result::Ok((&input[..len], &input[len..]))
+            }
+            None => Result::Err(
+                ParseError::new(format!("Expected pattern {}", self.regex.as_str()), input)
+                    .with_expected(Expected::Pattern(self.regex.as_str().to_string())),
+            ),
+        }
+    }
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+    fn describe(&self, _grammar: &mut Grammar) -> Syntax {
+        Syntax::Pattern(self.regex.as_str().to_string())
+    }
 }
 
 struct AndThenParser<'a, Output1, Output2> {
@@ -182,26 +356,32 @@ pub fn and_then<'a, Output1: 'a, Output2: 'a>(
 
 impl<'a, Output1: 'a, Output2: 'a> Parser<'a> for AndThenParser<'a, Output1, Output2> {
     type Output = (Output1, Output2);
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
-        let result1 = self.parser_a.parse(input);
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let result1 = self.parser_a.parse_in(input, session);
         match result1 {
             Ok((success1, remaining)) => {
-                let result2 = self.parser_b.parse(remaining);
+                let result2 = self.parser_b.parse_in(remaining, session);
                 match result2 {
                     Ok((success2, remaining)) => {
                         let x = (success1, success2);
                         Ok((x, remaining))
                     }
-                    Err(error) => Err(format!("Then 2nd : {}", error)),
+                    Err(error) => Err(error.prefixed("Then 2nd")),
                 }
             }
-            Err(error) => Err(format!("Then 1st : {}", error)),
+            Err(error) => Err(error.prefixed("Then 1st")),
         }
     }
 
     fn to_rc(self) -> RcParser<'a, Self::Output> {
         Rc::new(self)
     }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        let a = self.parser_a.describe(grammar);
+        let b = self.parser_b.describe(grammar);
+        Syntax::sequence(vec![a, b])
+    }
 }
 
 struct ChoiceParser<'a, Output> {
@@ -210,20 +390,51 @@ struct ChoiceParser<'a, Output> {
 
 impl<'a, Output: 'a> Parser<'a> for ChoiceParser<'a, Output> {
     type Output = Output;
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let checkpoint = session.checkpoint();
+        // The error from the alternative that got furthest, with the
+        // expectations of every alternative that failed at the same place.
+        let mut furthest: Option<ParseError> = None;
         for p in &self.parsers {
-            let result = p.parse(input);
+            session.step(input)?;
+            let result = p.parse_in(input, session);
             match result {
-                Ok(success) => return Ok(success),
-                Err(_) => continue,
+                Ok(success) => {
+                    if let Some(error) = furthest {
+                        session.discard(error);
+                    }
+                    return Ok(success);
+                }
+                Err(error) if error.is_fatal() => return Err(error),
+                Err(error) => {
+                    session.rollback(checkpoint);
+                    furthest = Some(match furthest {
+                        Some(best) => best.furthest(error),
+                        None => error,
+                    });
+                }
+            }
+        }
+        match furthest {
+            Some(error) if error.remaining < input.len() => Err(error),
+            furthest => {
+                let error = ParseError::new("Expected one of the parsers to succeed", input);
+                Err(match furthest {
+                    Some(furthest) => error.furthest(furthest),
+                    None => error,
+                })
             }
         }
-        Err("Expected one of the parsers to succeed".to_string())
     }
 
     fn to_rc(self) -> RcParser<'a, Self::Output> {
         Rc::new(self)
     }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        let alternatives = self.parsers.iter().map(|p| p.describe(grammar)).collect();
+        Syntax::choice(alternatives)
+    }
 }
 
 struct MapParser<'a, F, Input, Output>
@@ -239,20 +450,24 @@ where
     F: Fn(Input) -> Output,
 {
     type Output = Output;
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
-        let result = self.parser.parse(input);
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let result = self.parser.parse_in(input, session);
         match result {
             Ok((success, remaining)) => {
                 let mapped = (self.f)(success);
                 Ok((mapped, remaining))
             }
-            Err(error) => Err(format!("MapParser : {}", error)),
+            Err(error) => Err(error.prefixed("MapParser")),
         }
     }
 
     fn to_rc(self) -> RcParser<'a, Self::Output> {
         Rc::new(self)
     }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.parser.describe(grammar)
+    }
 }
 
 struct OptionParser<'a, Output> {
@@ -261,17 +476,27 @@ struct OptionParser<'a, Output> {
 
 impl<'a, Output: 'a> Parser<'a> for OptionParser<'a, Output> {
     type Output = Option<Output>;
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
-        let result1 = self.parser.parse(input);
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let checkpoint = session.checkpoint();
+        let result1 = self.parser.parse_in(input, session);
         match result1 {
             Ok((success, remaining)) => Result::Ok((Some(success), remaining)),
-            Err(_) => Result::Ok((None, input)),
+            Err(error) if error.is_fatal() => Err(error),
+            Err(error) => {
+                session.rollback(checkpoint);
+                session.discard(error);
+                Result::Ok((None, input))
+            }
         }
     }
 
     fn to_rc(self) -> RcParser<'a, Self::Output> {
         Rc::new(self)
     }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        Syntax::Optional(Box::new(self.parser.describe(grammar)))
+    }
 }
 
 struct ManyParser<'a, Output> {
@@ -280,22 +505,35 @@ struct ManyParser<'a, Output> {
 
 impl<'a, Output: 'a> Parser<'a> for ManyParser<'a, Output> {
     type Output = Vec<Output>;
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
-        let mut result = self.parser.parse(input);
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let mut checkpoint = session.checkpoint();
+        let mut result = self.parser.parse_in(input, session);
         let mut values = Vec::new();
         let mut outerremaining = input;
 
         while let Ok((success, remaining)) = result {
             values.push(success);
             outerremaining = remaining;
-            result = self.parser.parse(remaining);
+            checkpoint = session.checkpoint();
+            result = self.parser.parse_in(remaining, session);
+        }
+        if let Err(error) = result {
+            if error.is_fatal() {
+                return Err(error);
+            }
+            session.discard(error);
         }
+        session.rollback(checkpoint);
         Result::Ok((values, outerremaining))
     }
 
     fn to_rc(self) -> RcParser<'a, Self::Output> {
         Rc::new(self)
     }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        Syntax::Many(Box::new(self.parser.describe(grammar)))
+    }
 }
 
 struct Many1Parser<'a, Output> {
@@ -304,12 +542,12 @@ struct Many1Parser<'a, Output> {
 
 impl<'a, Output: 'a> Parser<'a> for Many1Parser<'a, Output> {
     type Output = Vec<Output>;
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
-        let result = self.parser.parse(input);
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let result = self.parser.parse_in(input, session);
         let many_parser = self.parser.clone().many();
         match result {
             Ok((success, remaining)) => {
-                let (mut result, remain) = many_parser.parse(remaining).unwrap();
+                let (mut result, remain) = many_parser.parse_in(remaining, session)?;
                 result.insert(0, success);
                 Ok((result, remain))
             }
@@ -320,6 +558,159 @@ impl<'a, Output: 'a> Parser<'a> for Many1Parser<'a, Output> {
     fn to_rc(self) -> RcParser<'a, Self::Output> {
         Rc::new(self)
     }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        Syntax::Many1(Box::new(self.parser.describe(grammar)))
+    }
+}
+
+struct ChainParser<'a, Output, F> {
+    term: RcParser<'a, Output>,
+    op: RcParser<'a, F>,
+    right: bool,
+}
+
+impl<'a, Output: 'a, F: 'a> Parser<'a> for ChainParser<'a, Output, F>
+where
+    F: Fn(Output, Output) -> Output,
+{
+    type Output = Output;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let (first, mut remaining) = self.term.parse_in(input, session)?;
+        let mut rest = Vec::new();
+        loop {
+            let checkpoint = session.checkpoint();
+            let next = self
+                .op
+                .parse_in(remaining, session)
+                .and_then(|(op, after_op)| {
+                    let (term, after_term) = self.term.parse_in(after_op, session)?;
+                    Ok(((op, term), after_term))
+                });
+            match next {
+                Ok((pair, after)) => {
+                    rest.push(pair);
+                    remaining = after;
+                }
+                Err(error) if error.is_fatal() => return Err(error),
+                Err(error) => {
+                    session.rollback(checkpoint);
+                    session.discard(error);
+                    break;
+                }
+            }
+        }
+
+        let value = if self.right {
+            // Shift each operator onto the term before it, then fold from
+            // the right: a op1 b op2 c => a op1 (b op2 c).
+            let mut terms = vec![first];
+            let mut ops = Vec::new();
+            for (op, term) in rest {
+                ops.push(op);
+                terms.push(term);
+            }
+            let mut value = terms.pop().unwrap();
+            while let Some(op) = ops.pop() {
+                value = op(terms.pop().unwrap(), value);
+            }
+            value
+        } else {
+            rest.into_iter()
+                .fold(first, |value, (op, term)| op(value, term))
+        };
+        Ok((value, remaining))
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        let term = self.term.describe(grammar);
+        let op = self.op.describe(grammar);
+        Syntax::sequence(vec![
+            term.clone(),
+            Syntax::Many(Box::new(Syntax::sequence(vec![op, term]))),
+        ])
+    }
+}
+
+struct NamedParser<'a, Output> {
+    name: &'static str,
+    parser: RcParser<'a, Output>,
+}
+
+impl<'a, Output: 'a> Parser<'a> for NamedParser<'a, Output> {
+    type Output = Output;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        self.parser
+            .parse_in(input, session)
+            .map_err(|error| error.in_rule(self.name, input))
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        grammar.named_rule(self.name, |grammar| self.parser.describe(grammar))
+    }
+}
+
+struct TokenParser<'a, Output> {
+    kind: &'static str,
+    parser: RcParser<'a, Output>,
+}
+
+impl<'a, Output: 'a> Parser<'a> for TokenParser<'a, Output> {
+    type Output = Output;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let checkpoint = session.checkpoint();
+        let (output, remaining) = self.parser.parse_in(input, session)?;
+        session.rollback(checkpoint);
+        session.token(self.kind, input, remaining);
+        Ok((output, remaining))
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.parser.describe(grammar)
+    }
+}
+
+struct NodeParser<'a, Output> {
+    kind: &'static str,
+    parser: RcParser<'a, Output>,
+}
+
+impl<'a, Output: 'a> Parser<'a> for NodeParser<'a, Output> {
+    type Output = Output;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let checkpoint = session.checkpoint();
+        session.event(Event::Start(self.kind));
+        match self.parser.parse_in(input, session) {
+            Ok(success) => {
+                session.event(Event::Finish);
+                Ok(success)
+            }
+            Err(error) => {
+                session.rollback(checkpoint);
+                Err(error)
+            }
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.parser.describe(grammar)
+    }
 }
 
 pub struct ForwardParser<'a, Output> {
@@ -328,13 +719,14 @@ pub struct ForwardParser<'a, Output> {
 
 impl<'a, Output: 'a> Parser<'a> for ForwardParser<'a, Output> {
     type Output = Output;
-    fn parse(&self, input: &'a str) -> Result<(Self::Output, &'a str), String> {
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        session.step(input)?;
         let p = self.parser.as_ref();
         match p {
-            Some(parser) => parser.parse(input),
+            Some(parser) => session.nested(input, || parser.parse_in(input, session)),
             None => {
                 println!("Failed because empty parser");
-                Result::Err("Forward Parser not implemented".to_string())
+                Result::Err(ParseError::new("Forward Parser not implemented", input))
             }
         }
     }
@@ -342,6 +734,14 @@ impl<'a, Output: 'a> Parser<'a> for ForwardParser<'a, Output> {
     fn to_rc(self) -> RcParser<'a, Self::Output> {
         Rc::new(self)
     }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        let id = self as *const Self as usize;
+        match &self.parser {
+            Some(parser) => grammar.forward_rule(id, |grammar| parser.describe(grammar)),
+            None => Syntax::Opaque,
+        }
+    }
 }
 
 pub fn forward<'a, Output>() -> Rc<ForwardParser<'a, Output>> {
@@ -366,6 +766,17 @@ pub fn pstring<'a>(string: &'static str) -> RcParser<'a, &'static str> {
     StringParser { string }.to_rc()
 }
 
+/// Matches a regular expression at the start of the input and returns the
+/// matched slice. See the `regex` module for the supported syntax.
+///
+/// # Panics
+///
+/// Panics if `pattern` is not a valid regular expression.
+pub fn pattern<'a>(pattern: &str) -> RcParser<'a, &'a str> {
+    let regex = Regex::new(pattern).unwrap_or_else(|error| panic!("{error}: {pattern}"));
+    PatternParser { regex }.to_rc()
+}
+
 pub fn choice<'a, Output: 'a>(parsers: Vec<RcParser<'a, Output>>) -> RcParser<'a, Output> {
     ChoiceParser { parsers }.to_rc()
 }
@@ -378,6 +789,7 @@ pub fn any_of<'a>(chars: &[char]) -> RcParser<'a, char> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::parser_combinator::error::ErrorKind;
     #[test]
     fn char_parse() {
         let parse_a = pchar('a');
@@ -392,6 +804,13 @@ mod tests {
         assert_eq!(result, Result::Ok(('a', "b")));
     }
 
+    #[test]
+    fn char_parse_multibyte() {
+        let parse_pound = pchar('£');
+        let result = parse_pound.parse("£5");
+        assert_eq!(result, Result::Ok(('£', "5")));
+    }
+
     #[test]
     fn str_parse() {
         let parse_hello = pstring("hello");
@@ -501,6 +920,34 @@ mod tests {
         assert_eq!(result, Result::Ok(("SomeValue".to_string(), " A")));
     }
 
+    #[test]
+    fn pattern_parse() {
+        let number = pattern(r"[0-9]+(\.[0-9]+)?");
+        let result = number.parse("3.14 rest");
+        assert_eq!(result, Result::Ok(("3.14", " rest")));
+    }
+
+    #[test]
+    fn pattern_parse_error_is_positioned() {
+        let source = "x = abc";
+        let number = pattern(r"[0-9]+");
+        let assign = pstring("x = ").right(number);
+
+        let error = assign.parse(source).unwrap_err();
+        assert_eq!(error.offset(source), 4);
+        assert_eq!(error.line_col(source), (1, 5));
+    }
+
+    #[test]
+    fn sep_by_test() {
+        let list = pattern("[0-9]+").sep_by(pchar(',').ws());
+
+        assert_eq!(list.parse("1, 2,3)"), Ok((vec!["1", "2", "3"], ")")));
+        assert_eq!(list.parse(")"), Ok((vec![], ")")));
+        assert_eq!(list.parse("1,)"), Ok((vec!["1"], ",)")));
+        assert!(pattern("[0-9]+").sep_by1(pchar(',')).parse(")").is_err());
+    }
+
     #[test]
     fn between_test() {
         let foo = pstring("foo");
@@ -511,4 +958,58 @@ mod tests {
 
         assert_eq!(result, Result::Ok(("foo", "")));
     }
+
+    fn number<'a>() -> RcParser<'a, i64> {
+        pattern("[0-9]+").map(|digits| digits.parse::<i64>().unwrap())
+    }
+
+    #[test]
+    fn chainl1_is_left_associative() {
+        let minus = pchar('-')
+            .ws()
+            .map(|_| (|a, b| a - b) as fn(i64, i64) -> i64);
+        let expr = number().ws().chainl1(minus);
+
+        assert_eq!(expr.parse("1 - 2 - 3"), Ok((-4, "")));
+        assert_eq!(expr.parse("7"), Ok((7, "")));
+        assert_eq!(expr.parse("7 -"), Ok((7, "-")));
+    }
+
+    #[test]
+    fn chainr1_is_right_associative() {
+        let power = pchar('^')
+            .ws()
+            .map(|_| (|a: i64, b: i64| a.pow(b as u32)) as fn(i64, i64) -> i64);
+        let expr = number().ws().chainr1(power);
+
+        assert_eq!(expr.parse("2 ^ 3 ^ 2"), Ok((512, "")));
+        assert_eq!(expr.parse("2 ^ 3"), Ok((8, "")));
+    }
+
+    #[test]
+    fn step_budget_is_not_swallowed_by_choice() {
+        let parser = pstring("ab").or(pstring("ac")).many();
+        let session = ParseSession::new().max_steps(5);
+
+        let error = session.parse(&parser, "acacacac").unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ResourceExhausted);
+        assert_eq!(error.message, "Step budget of 5 exhausted");
+
+        let session = ParseSession::new().max_steps(100);
+        let (result, _) = session.parse(&parser, "acacacac").unwrap();
+        assert_eq!(result.len(), 4);
+    }
+    #[test]
+    fn deadline_aborts_a_long_parse() {
+        let input = "a".repeat(5000);
+        let parser = pchar('a').many();
+        let session = ParseSession::new().deadline(std::time::Instant::now());
+
+        let error = session.parse(&parser, &input).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::ResourceExhausted);
+        assert_eq!(error.message, "Parse deadline exceeded");
+
+        // The clock is only read every so many steps.
+        assert_eq!(session.parse(&parser, "aaa").map(|(a, _)| a.len()), Ok(3));
+    }
 }
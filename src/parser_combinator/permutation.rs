@@ -0,0 +1,132 @@
+// Parsers for items that may appear in any order, each at most once.
+
+use std::rc::Rc;
+
+use super::error::ParseError;
+use super::grammar::{Grammar, Syntax};
+use super::parser::{ParseResult, Parser, RcParser};
+use super::session::ParseSession;
+
+pub struct PermutationParser<T> {
+    parsers: T,
+}
+
+/// Accepts every parser of the tuple once, in any order, and returns their
+/// outputs in declaration order. Items that can match without consuming
+/// input, such as `p.optional()`, may be left out.
+pub fn permutation<'a, T>(parsers: T) -> RcParser<'a, <PermutationParser<T> as Parser<'a>>::Output>
+where
+    PermutationParser<T>: Parser<'a> + 'a,
+{
+    PermutationParser { parsers }.to_rc()
+}
+
+fn missing<'a, P: Parser<'a> + ?Sized>(index: usize, parser: &P, input: &str) -> ParseError {
+    let item = parser.describe(&mut Grammar::default());
+    ParseError::new(
+        format!("Permutation is missing item {} ({})", index + 1, item),
+        input,
+    )
+}
+
+macro_rules! permutation_impl {
+    ($($T:ident $i:tt),+) => {
+        impl<'a, $($T: 'a),+> Parser<'a> for PermutationParser<($(RcParser<'a, $T>,)+)> {
+            type Output = ($($T,)+);
+            fn parse_in(
+                &self,
+                input: &'a str,
+                session: &ParseSession,
+            ) -> ParseResult<'a, Self::Output> {
+                let mut outputs = ($(None::<$T>,)+);
+                let mut remaining = input;
+                // Keep taking the first item that consumes input, until none does.
+                'next: loop {
+                    $(
+                        if outputs.$i.is_none() {
+                            let checkpoint = session.checkpoint();
+                            match self.parsers.$i.parse_in(remaining, session) {
+                                Ok((value, rest)) if rest.len() < remaining.len() => {
+                                    outputs.$i = Some(value);
+                                    remaining = rest;
+                                    continue 'next;
+                                }
+                                Err(error) if error.is_fatal() => return Err(error),
+                                _ => session.rollback(checkpoint),
+                            }
+                        }
+                    )+
+                    break;
+                }
+                // Whatever is left must match here without consuming input.
+                let output = ($(
+                    match outputs.$i {
+                        Some(value) => value,
+                        None => match self.parsers.$i.parse_in(remaining, session) {
+                            Ok((value, rest)) => {
+                                remaining = rest;
+                                value
+                            }
+                            Err(error) if error.is_fatal() => return Err(error),
+                            Err(_) => return Err(missing($i, &self.parsers.$i, remaining)),
+                        },
+                    },
+                )+);
+                Ok((output, remaining))
+            }
+
+            fn to_rc(self) -> RcParser<'a, Self::Output> {
+                Rc::new(self)
+            }
+
+            // Described by the declaration order, which is one of the
+            // accepted orders.
+            fn describe(&self, grammar: &mut Grammar) -> Syntax {
+                Syntax::sequence(vec![$(self.parsers.$i.describe(grammar)),+])
+            }
+        }
+    };
+}
+
+permutation_impl!(A 0, B 1);
+permutation_impl!(A 0, B 1, C 2);
+permutation_impl!(A 0, B 1, C 2, D 3);
+permutation_impl!(A 0, B 1, C 2, D 3, E 4);
+permutation_impl!(A 0, B 1, C 2, D 3, E 4, F 5);
+permutation_impl!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+permutation_impl!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser_combinator::parser::*;
+
+    fn flags<'a>() -> RcParser<'a, (&'static str, Option<&'static str>, &'static str)> {
+        permutation((
+            pstring("-a").ws(),
+            pstring("-b").ws().optional(),
+            pstring("-c").ws(),
+        ))
+    }
+
+    #[test]
+    fn permutation_accepts_any_order() {
+        let expected = ("-a", Some("-b"), "-c");
+        assert_eq!(flags().parse("-a -b -c"), Ok((expected, "")));
+        assert_eq!(flags().parse("-c -a -b"), Ok((expected, "")));
+        assert_eq!(flags().parse("-b -c -a"), Ok((expected, "")));
+        assert_eq!(flags().parse("-c -a"), Ok((("-a", None, "-c"), "")));
+    }
+
+    #[test]
+    fn permutation_takes_each_item_once() {
+        assert_eq!(flags().parse("-a -c -a"), Ok((("-a", None, "-c"), "-a")));
+    }
+
+    #[test]
+    fn permutation_reports_missing_item() {
+        let error = flags().parse("-b -a").unwrap_err();
+        assert_eq!(error.message, "Permutation is missing item 3 ('-c' ws)");
+        assert_eq!(error.remaining, 0);
+    }
+}
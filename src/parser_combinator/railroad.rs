@@ -0,0 +1,421 @@
+// Renders a `Grammar` as SVG railroad diagrams, one diagram per rule.
+//
+// Every item is laid out around a horizontal "main line": `up` and `down`
+// are the space it needs above and below that line, and it is drawn from
+// its left edge at `x` to `x + width`.
+
+use std::fmt::Write;
+
+use super::grammar::{slashed_pattern, Grammar, Syntax};
+
+const CHAR_WIDTH: f64 = 8.0;
+const BOX_HEIGHT: f64 = 24.0;
+const BOX_PADDING: f64 = 10.0;
+const GAP: f64 = 10.0;
+const ARC: f64 = 10.0;
+const ROW_GAP: f64 = 10.0;
+const MARGIN: f64 = 20.0;
+const TITLE_HEIGHT: f64 = 24.0;
+
+enum Kind {
+    Terminal(String),
+    NonTerminal(String),
+    Skip,
+    // A dead end, for a choice with no alternatives.
+    Never,
+    Sequence(Vec<Item>),
+    Choice(Vec<Item>),
+    Loop(Box<Item>),
+}
+
+struct Item {
+    kind: Kind,
+    width: f64,
+    up: f64,
+    down: f64,
+}
+
+impl Item {
+    fn boxed(text: String, terminal: bool) -> Item {
+        let width = text.chars().count() as f64 * CHAR_WIDTH + 2.0 * BOX_PADDING;
+        let kind = if terminal {
+            Kind::Terminal(text)
+        } else {
+            Kind::NonTerminal(text)
+        };
+        Item {
+            kind,
+            width,
+            up: BOX_HEIGHT / 2.0,
+            down: BOX_HEIGHT / 2.0,
+        }
+    }
+
+    fn skip() -> Item {
+        Item {
+            kind: Kind::Skip,
+            width: 0.0,
+            up: 0.0,
+            down: 0.0,
+        }
+    }
+
+    fn sequence(items: Vec<Item>) -> Item {
+        let gaps = items.len().saturating_sub(1) as f64 * GAP;
+        Item {
+            width: items.iter().map(|item| item.width).sum::<f64>() + gaps,
+            up: items.iter().map(|item| item.up).fold(0.0, f64::max),
+            down: items.iter().map(|item| item.down).fold(0.0, f64::max),
+            kind: Kind::Sequence(items),
+        }
+    }
+
+    fn never() -> Item {
+        Item {
+            kind: Kind::Never,
+            width: 2.0 * ARC,
+            up: ARC,
+            down: ARC,
+        }
+    }
+
+    // A choice with no alternatives, such as `any_of(&[])`, never matches,
+    // so the line stops there.
+    fn choice(items: Vec<Item>) -> Item {
+        if items.is_empty() {
+            return Item::never();
+        }
+        let width = items.iter().map(|item| item.width).fold(0.0, f64::max) + 4.0 * ARC;
+        let up = items[0].up;
+        let down = items[0].down
+            + items[1..]
+                .iter()
+                .map(|item| ROW_GAP + item.up + item.down)
+                .sum::<f64>();
+        Item {
+            kind: Kind::Choice(items),
+            width,
+            up,
+            down,
+        }
+    }
+
+    fn one_or_more(item: Item) -> Item {
+        Item {
+            width: item.width + 4.0 * ARC,
+            up: item.up,
+            down: item.down + ROW_GAP,
+            kind: Kind::Loop(Box::new(item)),
+        }
+    }
+
+    fn draw(&self, svg: &mut String, x: f64, y: f64) {
+        match &self.kind {
+            Kind::Terminal(text) | Kind::NonTerminal(text) => {
+                let radius = if matches!(self.kind, Kind::Terminal(_)) {
+                    BOX_HEIGHT / 2.0
+                } else {
+                    0.0
+                };
+                let class = if radius > 0.0 {
+                    "terminal"
+                } else {
+                    "nonterminal"
+                };
+                let _ = writeln!(
+                    svg,
+                    r#"<g class="{}"><rect x="{}" y="{}" width="{}" height="{}" rx="{}"/><text x="{}" y="{}">{}</text></g>"#,
+                    class,
+                    x,
+                    y - BOX_HEIGHT / 2.0,
+                    self.width,
+                    BOX_HEIGHT,
+                    radius,
+                    x + self.width / 2.0,
+                    y + 4.0,
+                    escape(text)
+                );
+            }
+            Kind::Skip => {}
+            Kind::Never => {
+                let _ = writeln!(
+                    svg,
+                    r#"<path class="never" d="M{} {} H{} M{} {} V{}"/>"#,
+                    x,
+                    y,
+                    x + ARC,
+                    x + ARC,
+                    y - ARC,
+                    y + ARC
+                );
+            }
+            Kind::Sequence(items) => {
+                let mut x = x;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        line(svg, x, y, x + GAP, y);
+                        x += GAP;
+                    }
+                    item.draw(svg, x, y);
+                    x += item.width;
+                }
+            }
+            Kind::Choice(items) => {
+                let right = x + self.width;
+                let mut item_y = y;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        item_y += items[i - 1].down + ROW_GAP + item.up;
+                        let _ = writeln!(
+                            svg,
+                            r#"<path d="M{} {} a{} {} 0 0 1 {} {} V{} a{} {} 0 0 0 {} {}"/>"#,
+                            x,
+                            y,
+                            ARC,
+                            ARC,
+                            ARC,
+                            ARC,
+                            item_y - ARC,
+                            ARC,
+                            ARC,
+                            ARC,
+                            ARC
+                        );
+                        let _ = writeln!(
+                            svg,
+                            r#"<path d="M{} {} a{} {} 0 0 0 {} {} V{} a{} {} 0 0 1 {} {}"/>"#,
+                            right - 2.0 * ARC,
+                            item_y,
+                            ARC,
+                            ARC,
+                            ARC,
+                            -ARC,
+                            y + ARC,
+                            ARC,
+                            ARC,
+                            ARC,
+                            -ARC
+                        );
+                    } else {
+                        line(svg, x, y, x + 2.0 * ARC, y);
+                        line(svg, right - 2.0 * ARC, y, right, y);
+                    }
+                    item.draw(svg, x + 2.0 * ARC, item_y);
+                    line(
+                        svg,
+                        x + 2.0 * ARC + item.width,
+                        item_y,
+                        right - 2.0 * ARC,
+                        item_y,
+                    );
+                }
+            }
+            Kind::Loop(item) => {
+                let right = x + self.width;
+                let loop_y = y + item.down + ROW_GAP;
+                line(svg, x, y, x + 2.0 * ARC, y);
+                item.draw(svg, x + 2.0 * ARC, y);
+                line(svg, x + 2.0 * ARC + item.width, y, right, y);
+                let _ = writeln!(
+                    svg,
+                    r#"<path d="M{} {} a{} {} 0 0 1 {} {} V{} a{} {} 0 0 1 {} {} H{} a{} {} 0 0 1 {} {} V{} a{} {} 0 0 1 {} {}"/>"#,
+                    right - 2.0 * ARC,
+                    y,
+                    ARC,
+                    ARC,
+                    ARC,
+                    ARC,
+                    loop_y - ARC,
+                    ARC,
+                    ARC,
+                    -ARC,
+                    ARC,
+                    x + 2.0 * ARC,
+                    ARC,
+                    ARC,
+                    -ARC,
+                    -ARC,
+                    y + ARC,
+                    ARC,
+                    ARC,
+                    ARC,
+                    -ARC
+                );
+            }
+        }
+    }
+}
+
+fn line(svg: &mut String, x1: f64, y1: f64, x2: f64, y2: f64) {
+    if x1 != x2 || y1 != y2 {
+        let _ = writeln!(svg, r#"<path d="M{} {} L{} {}"/>"#, x1, y1, x2, y2);
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn char_label(c: char) -> String {
+    match c {
+        '\n' => "\\n".to_string(),
+        '\r' => "\\r".to_string(),
+        '\t' => "\\t".to_string(),
+        ' ' => "space".to_string(),
+        c => c.to_string(),
+    }
+}
+
+fn layout(syntax: &Syntax) -> Item {
+    match syntax {
+        Syntax::Char(c) => Item::boxed(char_label(*c), true),
+        Syntax::String(s) => Item::boxed(s.clone(), true),
+        Syntax::Pattern(p) => Item::boxed(slashed_pattern(p), true),
+        Syntax::Rule(name) => Item::boxed(name.clone(), false),
+        Syntax::Opaque => Item::boxed("?".to_string(), false),
+        Syntax::Sequence(items) if items.is_empty() => Item::skip(),
+        Syntax::Sequence(items) => Item::sequence(items.iter().map(layout).collect()),
+        Syntax::Choice(items) => {
+            let chars: Option<Vec<String>> = items
+                .iter()
+                .map(|item| match item {
+                    Syntax::Char(c) => Some(char_label(*c)),
+                    _ => None,
+                })
+                .collect();
+            match chars {
+                // A long list of single characters reads better as one box.
+                Some(chars) if chars.len() > 4 => {
+                    Item::boxed(format!("one of {}", chars.join(" ")), true)
+                }
+                _ => Item::choice(items.iter().map(layout).collect()),
+            }
+        }
+        Syntax::Optional(item) => Item::choice(vec![Item::skip(), layout(item)]),
+        Syntax::Many(item) => Item::choice(vec![Item::skip(), Item::one_or_more(layout(item))]),
+        Syntax::Many1(item) => Item::one_or_more(layout(item)),
+    }
+}
+
+impl Grammar {
+    /// Renders every rule as a railroad diagram, stacked in a single SVG
+    /// document. Terminals are drawn as rounded boxes, references to other
+    /// rules as square boxes.
+    pub fn to_railroad_svg(&self) -> String {
+        let diagrams: Vec<(&str, Item)> = self
+            .rules
+            .iter()
+            .map(|rule| (rule.name.as_str(), layout(&rule.syntax)))
+            .collect();
+
+        let width = diagrams
+            .iter()
+            .map(|(_, item)| item.width + 4.0 * GAP)
+            .fold(0.0, f64::max)
+            + 2.0 * MARGIN;
+        let height = diagrams
+            .iter()
+            .map(|(_, item)| TITLE_HEIGHT + item.up + item.down + MARGIN)
+            .sum::<f64>()
+            + MARGIN;
+
+        let mut svg = String::new();
+        let _ = writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#,
+            width, height, width, height
+        );
+        svg.push_str(concat!(
+            "<style>\n",
+            "path { fill: none; stroke: #333; stroke-width: 2; }\n",
+            "rect { fill: #fff; stroke: #333; stroke-width: 2; }\n",
+            ".terminal rect { fill: #eef; }\n",
+            "text { font: 14px monospace; text-anchor: middle; }\n",
+            "text.rule { font-weight: bold; text-anchor: start; }\n",
+            "</style>\n"
+        ));
+
+        let mut y = MARGIN;
+        for (name, item) in &diagrams {
+            let _ = writeln!(
+                svg,
+                r#"<text class="rule" x="{}" y="{}">{}</text>"#,
+                MARGIN,
+                y + 14.0,
+                escape(name)
+            );
+            let line_y = y + TITLE_HEIGHT + item.up;
+            let start = MARGIN;
+            let end = MARGIN + item.width + 4.0 * GAP;
+            let _ = writeln!(
+                svg,
+                r#"<path d="M{} {} V{} M{} {} V{}"/>"#,
+                start,
+                line_y - ARC,
+                line_y + ARC,
+                end,
+                line_y - ARC,
+                line_y + ARC
+            );
+            line(&mut svg, start, line_y, start + 2.0 * GAP, line_y);
+            item.draw(&mut svg, start + 2.0 * GAP, line_y);
+            line(&mut svg, end - 2.0 * GAP, line_y, end, line_y);
+            y += TITLE_HEIGHT + item.up + item.down + MARGIN;
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser_combinator::grammar::Grammar;
+    use crate::parser_combinator::parser::*;
+
+    #[test]
+    fn railroad_has_a_diagram_per_rule() {
+        let digit = any_of(&['0', '1']).named("digit");
+        let list = digit
+            .clone()
+            .then(pchar(',').right(digit).many())
+            .named("list");
+        let svg = Grammar::of(&list).to_railroad_svg();
+
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains(r#"<text class="rule" x="20" y="34">list</text>"#));
+        assert!(svg.contains(">digit</text>"));
+        assert!(svg.contains(">,</text>"));
+    }
+
+    #[test]
+    fn empty_choices_render() {
+        let never = choice::<char>(vec![]).named("never");
+        let none = any_of(&[]).named("none");
+        let rule = never.then(none).named("rule");
+        let svg = Grammar::of(&rule).to_railroad_svg();
+
+        assert!(svg.contains(">never</text>"));
+        assert!(svg.contains(">none</text>"));
+        // Each rule ends in a dead end rather than passing straight through.
+        assert_eq!(svg.matches(r#"<path class="never""#).count(), 2);
+        assert_eq!(
+            Grammar::of(&rule).to_ebnf(),
+            "rule  ::= never none\nnever ::= ? never ?\nnone  ::= ? never ?\n"
+        );
+    }
+
+    #[test]
+    fn slashes_in_patterns_are_escaped() {
+        let comment = pattern(r"//[^/\n]*").named("comment");
+        let svg = Grammar::of(&comment).to_railroad_svg();
+        assert!(svg.contains(r">/\/\/[^\/\n]*/</text>"));
+        assert_eq!(
+            Grammar::of(&pattern(r"a\/b")).to_ebnf(),
+            "start ::= /a\\/b/\n"
+        );
+    }
+}
@@ -0,0 +1,610 @@
+// A small regular expression engine used by `pattern()`.
+//
+// Supported syntax: literals, `.`, `[...]` / `[^...]` classes with ranges,
+// `\d \w \s` (and their negations outside classes), escaped punctuation,
+// `\n \t \r`, groups `(...)` / `(?:...)`, alternation `|`, the quantifiers
+// `* + ? {n} {n,} {n,m}`, and the anchors `^` and `$`.
+//
+// Patterns are compiled to an NFA and simulated one character at a time, so
+// matching is linear in the length of the input. A pattern always matches at
+// the start of the input and the longest match wins.
+
+use super::rng::Rng;
+
+const MAX_REPEAT: u32 = 1000;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Class {
+    negated: bool,
+    ranges: Vec<(char, char)>,
+}
+
+impl Class {
+    fn matches(&self, c: char) -> bool {
+        let found = self.ranges.iter().any(|(lo, hi)| *lo <= c && c <= *hi);
+        found != self.negated
+    }
+}
+
+const DIGIT: &[(char, char)] = &[('0', '9')];
+const WORD: &[(char, char)] = &[('a', 'z'), ('A', 'Z'), ('0', '9'), ('_', '_')];
+const SPACE: &[(char, char)] = &[(' ', ' '), ('\t', '\r')];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Empty,
+    Literal(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+    },
+}
+
+struct RegexParser<'p> {
+    chars: std::iter::Peekable<std::str::Chars<'p>>,
+}
+
+impl<'p> RegexParser<'p> {
+    fn error<T>(&self, message: &str) -> Result<T, String> {
+        Err(format!("Invalid pattern - {message}"))
+    }
+
+    fn alternation(&mut self) -> Result<Node, String> {
+        let mut branches = vec![self.concat()?];
+        while self.chars.peek() == Some(&'|') {
+            self.chars.next();
+            branches.push(self.concat()?);
+        }
+        if branches.len() == 1 {
+            Ok(branches.pop().unwrap())
+        } else {
+            Ok(Node::Alternate(branches))
+        }
+    }
+
+    fn concat(&mut self) -> Result<Node, String> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.chars.peek() {
+            if *c == '|' || *c == ')' {
+                break;
+            }
+            let atom = self.atom()?;
+            nodes.push(self.quantified(atom)?);
+        }
+        match nodes.len() {
+            0 => Ok(Node::Empty),
+            1 => Ok(nodes.pop().unwrap()),
+            _ => Ok(Node::Concat(nodes)),
+        }
+    }
+
+    fn quantified(&mut self, mut node: Node) -> Result<Node, String> {
+        loop {
+            let (min, max) = match self.chars.peek() {
+                Some('*') => (0, None),
+                Some('+') => (1, None),
+                Some('?') => (0, Some(1)),
+                Some('{') => {
+                    self.chars.next();
+                    let (min, max) = self.counted()?;
+                    node = self.repeat(node, min, max)?;
+                    continue;
+                }
+                _ => return Ok(node),
+            };
+            self.chars.next();
+            node = self.repeat(node, min, max)?;
+        }
+    }
+
+    fn repeat(&mut self, node: Node, min: u32, max: Option<u32>) -> Result<Node, String> {
+        if matches!(node, Node::Empty | Node::Start | Node::End) {
+            return self.error("nothing to repeat");
+        }
+        if self.chars.peek() == Some(&'?') {
+            return self.error("lazy quantifiers are not supported");
+        }
+        Ok(Node::Repeat {
+            node: Box::new(node),
+            min,
+            max,
+        })
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let mut digits = String::new();
+        while let Some(c) = self.chars.peek().filter(|c| c.is_ascii_digit()) {
+            digits.push(*c);
+            self.chars.next();
+        }
+        digits.parse().ok()
+    }
+
+    fn counted(&mut self) -> Result<(u32, Option<u32>), String> {
+        let min = match self.number() {
+            Some(min) => min,
+            None => return self.error("expected a repetition count after {"),
+        };
+        let max = match self.chars.next() {
+            Some('}') => Some(min),
+            Some(',') => {
+                let max = self.number();
+                if self.chars.next() != Some('}') {
+                    return self.error("unterminated repetition count");
+                }
+                max
+            }
+            _ => return self.error("unterminated repetition count"),
+        };
+        if min > MAX_REPEAT || max.is_some_and(|max| max > MAX_REPEAT) {
+            return self.error("repetition count is too large");
+        }
+        if max.is_some_and(|max| max < min) {
+            return self.error("repetition range is out of order");
+        }
+        Ok((min, max))
+    }
+
+    fn atom(&mut self) -> Result<Node, String> {
+        match self.chars.next() {
+            Some('(') => {
+                if self.chars.peek() == Some(&'?') {
+                    self.chars.next();
+                    if self.chars.next() != Some(':') {
+                        return self.error("only (?:...) groups are supported");
+                    }
+                }
+                let node = self.alternation()?;
+                if self.chars.next() != Some(')') {
+                    return self.error("missing )");
+                }
+                Ok(node)
+            }
+            Some('[') => self.class(),
+            Some('.') => Ok(Node::Any),
+            Some('^') => Ok(Node::Start),
+            Some('$') => Ok(Node::End),
+            Some('\\') => self.escape(),
+            Some(c @ ('*' | '+' | '?' | '{')) => {
+                self.error(&format!("nothing to repeat before {c}"))
+            }
+            Some(c) => Ok(Node::Literal(c)),
+            None => self.error("unexpected end of pattern"),
+        }
+    }
+
+    fn escape(&mut self) -> Result<Node, String> {
+        let set = |ranges: &[(char, char)], negated| {
+            Ok(Node::Class(Class {
+                negated,
+                ranges: ranges.to_vec(),
+            }))
+        };
+        match self.chars.next() {
+            Some('d') => set(DIGIT, false),
+            Some('w') => set(WORD, false),
+            Some('s') => set(SPACE, false),
+            Some('D') => set(DIGIT, true),
+            Some('W') => set(WORD, true),
+            Some('S') => set(SPACE, true),
+            Some(c) => self.escaped_char(c).map(Node::Literal),
+            None => self.error("trailing \\"),
+        }
+    }
+
+    fn escaped_char(&self, c: char) -> Result<char, String> {
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            c if c.is_ascii_punctuation() || c == ' ' => Ok(c),
+            c => self.error(&format!("unknown escape \\{c}")),
+        }
+    }
+
+    fn class_char(&mut self) -> Result<char, String> {
+        match self.chars.next() {
+            Some('\\') => match self.chars.next() {
+                Some(c) => self.escaped_char(c),
+                None => self.error("trailing \\"),
+            },
+            Some(c) => Ok(c),
+            None => self.error("missing ]"),
+        }
+    }
+
+    fn class(&mut self) -> Result<Node, String> {
+        let negated = self.chars.peek() == Some(&'^');
+        if negated {
+            self.chars.next();
+        }
+        let mut ranges = Vec::new();
+        let mut first = true;
+        loop {
+            match self.chars.peek() {
+                None => return self.error("missing ]"),
+                Some(']') if !first => {
+                    self.chars.next();
+                    break;
+                }
+                Some('\\') => {
+                    let mut lookahead = self.chars.clone();
+                    lookahead.next();
+                    let predefined = match lookahead.next() {
+                        Some('d') => Some(DIGIT),
+                        Some('w') => Some(WORD),
+                        Some('s') => Some(SPACE),
+                        _ => None,
+                    };
+                    if let Some(predefined) = predefined {
+                        self.chars = lookahead;
+                        ranges.extend_from_slice(predefined);
+                        first = false;
+                        continue;
+                    }
+                }
+                _ => {}
+            }
+            first = false;
+            let lo = self.class_char()?;
+            let mut lookahead = self.chars.clone();
+            if lookahead.next() == Some('-') && !matches!(lookahead.peek(), Some(']') | None) {
+                self.chars = lookahead;
+                let hi = self.class_char()?;
+                if hi < lo {
+                    return self.error(&format!("range {lo}-{hi} is out of order"));
+                }
+                ranges.push((lo, hi));
+            } else {
+                ranges.push((lo, lo));
+            }
+        }
+        Ok(Node::Class(Class { negated, ranges }))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Inst {
+    Char(char),
+    Any,
+    Class(Class),
+    Start,
+    End,
+    Split(usize, usize),
+    Jump(usize),
+    Match,
+}
+
+fn compile(node: &Node, program: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Literal(c) => program.push(Inst::Char(*c)),
+        Node::Any => program.push(Inst::Any),
+        Node::Class(class) => program.push(Inst::Class(class.clone())),
+        Node::Start => program.push(Inst::Start),
+        Node::End => program.push(Inst::End),
+        Node::Concat(nodes) => {
+            for node in nodes {
+                compile(node, program);
+            }
+        }
+        Node::Alternate(branches) => {
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 < branches.len() {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(branch, program);
+                    jumps.push(program.len());
+                    program.push(Inst::Jump(0));
+                    program[split] = Inst::Split(split + 1, program.len());
+                } else {
+                    compile(branch, program);
+                }
+            }
+            let end = program.len();
+            for jump in jumps {
+                program[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, min, max } => {
+            for _ in 0..*min {
+                compile(node, program);
+            }
+            match max {
+                None => {
+                    let split = program.len();
+                    program.push(Inst::Split(split + 1, 0));
+                    compile(node, program);
+                    program.push(Inst::Jump(split));
+                    program[split] = Inst::Split(split + 1, program.len());
+                }
+                Some(max) => {
+                    let mut splits = Vec::new();
+                    for _ in *min..*max {
+                        splits.push(program.len());
+                        program.push(Inst::Split(program.len() + 1, 0));
+                        compile(node, program);
+                    }
+                    let end = program.len();
+                    for split in splits {
+                        program[split] = Inst::Split(split + 1, end);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Regex {
+    source: String,
+    program: Vec<Inst>,
+}
+
+impl Regex {
+    pub fn new(pattern: &str) -> Result<Regex, String> {
+        let mut parser = RegexParser {
+            chars: pattern.chars().peekable(),
+        };
+        let node = parser.alternation()?;
+        if parser.chars.next().is_some() {
+            return parser.error("unbalanced )");
+        }
+        let mut program = Vec::new();
+        compile(&node, &mut program);
+        program.push(Inst::Match);
+        Ok(Regex {
+            source: pattern.to_string(),
+            program,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Length in bytes of the longest match at the start of `input`, if any.
+    pub fn match_len(&self, input: &str) -> Option<usize> {
+        let mut current = Threads::new(self.program.len());
+        let mut next = Threads::new(self.program.len());
+        let mut longest = None;
+
+        self.add_thread(&mut current, 0, 0, input);
+        let mut chars = input.char_indices();
+        loop {
+            if current
+                .pcs
+                .iter()
+                .any(|pc| self.program[*pc] == Inst::Match)
+            {
+                longest = Some(current.position);
+            }
+            let Some((position, c)) = chars.next() else {
+                break;
+            };
+            if current.pcs.is_empty() {
+                break;
+            }
+            let after = position + c.len_utf8();
+            next.clear();
+            for i in 0..current.pcs.len() {
+                let pc = current.pcs[i];
+                let matched = match &self.program[pc] {
+                    Inst::Char(expected) => *expected == c,
+                    Inst::Any => c != '\n',
+                    Inst::Class(class) => class.matches(c),
+                    _ => false,
+                };
+                if matched {
+                    self.add_thread(&mut next, pc + 1, after, input);
+                }
+            }
+            std::mem::swap(&mut current, &mut next);
+            current.position = after;
+        }
+        longest
+    }
+
+    fn add_thread(&self, threads: &mut Threads, pc: usize, position: usize, input: &str) {
+        if threads.seen[pc] == threads.generation {
+            return;
+        }
+        threads.seen[pc] = threads.generation;
+        match &self.program[pc] {
+            Inst::Jump(target) => self.add_thread(threads, *target, position, input),
+            Inst::Split(a, b) => {
+                self.add_thread(threads, *a, position, input);
+                self.add_thread(threads, *b, position, input);
+            }
+            Inst::Start if position == 0 => self.add_thread(threads, pc + 1, position, input),
+            Inst::End if position == input.len() => {
+                self.add_thread(threads, pc + 1, position, input)
+            }
+            Inst::Start | Inst::End => {}
+            _ => threads.pcs.push(pc),
+        }
+    }
+    /// A random string matched by the pattern. Loops are exited once the
+    /// sample gets long, and `$` is ignored.
+    pub(crate) fn sample(&self, rng: &mut Rng) -> String {
+        const SOFT_LIMIT: usize = 16;
+        let mut output = String::new();
+        let mut steps = 0;
+        let mut pc = 0;
+        loop {
+            steps += 1;
+            match &self.program[pc] {
+                Inst::Match => return output,
+                Inst::Char(c) => {
+                    output.push(*c);
+                    pc += 1;
+                }
+                Inst::Any => {
+                    output.push(PRINTABLE[rng.below(PRINTABLE.len())]);
+                    pc += 1;
+                }
+                Inst::Class(class) if !class.negated => {
+                    let total: u32 = class
+                        .ranges
+                        .iter()
+                        .map(|(lo, hi)| *hi as u32 - *lo as u32 + 1)
+                        .sum();
+                    let mut pick = rng.below(total as usize) as u32;
+                    for (lo, hi) in &class.ranges {
+                        let size = *hi as u32 - *lo as u32 + 1;
+                        if pick < size {
+                            output.extend(char::from_u32(*lo as u32 + pick));
+                            break;
+                        }
+                        pick -= size;
+                    }
+                    pc += 1;
+                }
+                Inst::Class(class) => {
+                    let allowed: Vec<char> = PRINTABLE
+                        .iter()
+                        .copied()
+                        .filter(|c| class.matches(*c))
+                        .collect();
+                    if allowed.is_empty() {
+                        // The class excludes every printable character, so
+                        // take the first character it does match.
+                        output.extend(
+                            (0..=char::MAX as u32)
+                                .filter_map(char::from_u32)
+                                .find(|c| class.matches(*c)),
+                        );
+                    } else {
+                        output.push(allowed[rng.below(allowed.len())]);
+                    }
+                    pc += 1;
+                }
+                Inst::Start | Inst::End => pc += 1,
+                Inst::Jump(target) => pc = *target,
+                Inst::Split(a, b) => {
+                    pc = if output.len() < SOFT_LIMIT && steps < 4 * SOFT_LIMIT && rng.below(2) == 0
+                    {
+                        *a
+                    } else {
+                        *b
+                    };
+                }
+            }
+        }
+    }
+}
+
+const PRINTABLE: &[char] = &[
+    ' ', '!', '#', '$', '%', '&', '(', ')', '*', '+', ',', '-', '.', '/', '0', '1', '2', '3', '4',
+    '5', '6', '7', '8', '9', ':', ';', '<', '=', '>', '?', '@', 'A', 'B', 'C', 'X', 'Y', 'Z', '[',
+    ']', '^', '_', 'a', 'b', 'c', 'x', 'y', 'z', '{', '|', '}', '~',
+];
+
+struct Threads {
+    pcs: Vec<usize>,
+    seen: Vec<usize>,
+    generation: usize,
+    position: usize,
+}
+
+impl Threads {
+    fn new(size: usize) -> Self {
+        Threads {
+            pcs: Vec::with_capacity(size),
+            seen: vec![0; size],
+            generation: 1,
+            position: 0,
+        }
+    }
+
+    fn clear(&mut self) {
+        self.pcs.clear();
+        self.generation += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn match_len(pattern: &str, input: &str) -> Option<usize> {
+        Regex::new(pattern).unwrap().match_len(input)
+    }
+
+    #[test]
+    fn literal_prefix() {
+        assert_eq!(match_len("abc", "abcdef"), Some(3));
+        assert_eq!(match_len("abc", "abd"), None);
+    }
+
+    #[test]
+    fn decimal_number() {
+        let number = r"[0-9]+(\.[0-9]+)?";
+        assert_eq!(match_len(number, "123.45 rest"), Some(6));
+        assert_eq!(match_len(number, "123. rest"), Some(3));
+        assert_eq!(match_len(number, ".5"), None);
+    }
+
+    #[test]
+    fn longest_alternative_wins() {
+        assert_eq!(match_len("a|ab|abc", "abcd"), Some(3));
+    }
+
+    #[test]
+    fn float_with_exponent() {
+        let float = r"-?\d+(\.\d+)?([eE][+\-]?\d+)?";
+        assert_eq!(match_len(float, "-1.5e-10,"), Some(8));
+        assert_eq!(match_len(float, "2E8"), Some(3));
+    }
+
+    #[test]
+    fn counted_repetition() {
+        let date = r"\d{4}-\d{2}-\d{2}";
+        assert_eq!(match_len(date, "2023-01-31T"), Some(10));
+        assert_eq!(match_len(date, "23-01-31"), None);
+        assert_eq!(match_len("a{2,3}", "aaaa"), Some(3));
+        assert_eq!(match_len("a{2,}", "aaaa"), Some(4));
+    }
+
+    #[test]
+    fn negated_class_and_anchors() {
+        assert_eq!(match_len("[^\"]*", "abc\"def"), Some(3));
+        assert_eq!(match_len("a*$", "aaa"), Some(3));
+        assert_eq!(match_len("a*$", "aab"), None);
+        assert_eq!(match_len("^a", "a"), Some(1));
+    }
+
+    #[test]
+    fn samples_match_the_pattern() {
+        let mut rng = Rng::new(7);
+        for pattern in [r"[a-c]+\d?", "x[^a-z]*y", "[^ -~]+"] {
+            let regex = Regex::new(pattern).unwrap();
+            for _ in 0..20 {
+                let sample = regex.sample(&mut rng);
+                assert_eq!(
+                    regex.match_len(&sample),
+                    Some(sample.len()),
+                    "{pattern}: {sample:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_patterns() {
+        assert!(Regex::new("(ab").is_err());
+        assert!(Regex::new("ab)").is_err());
+        assert!(Regex::new("*a").is_err());
+        assert!(Regex::new("[z-a]").is_err());
+        assert!(Regex::new("a{3,1}").is_err());
+        assert!(Regex::new(r"\q").is_err());
+    }
+}
@@ -0,0 +1,327 @@
+use std::any::Any;
+use std::cell::{Cell, RefCell};
+use std::ptr::NonNull;
+use std::time::Instant;
+
+use super::cst::Event;
+use super::error::ParseError;
+use super::parser::{ParseResult, Parser};
+
+/// State shared by every parser taking part in one parse.
+///
+/// `Parser::parse` runs in a fresh default session; build one explicitly to
+/// change how the parse is run.
+pub struct ParseSession<'s> {
+    source: Cell<&'s str>,
+    cst: bool,
+    events: RefCell<Vec<Event>>,
+    max_steps: Option<usize>,
+    deadline: Option<Instant>,
+    steps: Cell<usize>,
+    max_depth: usize,
+    depth: Cell<usize>,
+    grow_stack: bool,
+    indents: RefCell<Vec<usize>>,
+    furthest: RefCell<Option<ParseError>>,
+    state: Cell<Option<NonNull<dyn Any>>>,
+    state_borrowed: Cell<bool>,
+}
+
+/// Deep enough for any hand-written source, shallow enough to stay within
+/// the default 2MB thread stack in debug builds: the language grammar runs
+/// out of stack at a depth of about 85 there.
+pub const DEFAULT_MAX_DEPTH: usize = 64;
+
+// At least this much stack is left free before each nested parse: with
+// `grow_stack` by moving to a new segment this big, otherwise by failing.
+const STACK_RED_ZONE: usize = 128 * 1024;
+const STACK_SEGMENT: usize = 1024 * 1024;
+
+// Reading the clock on every step would dominate small parsers.
+const DEADLINE_CHECK_INTERVAL: usize = 1024;
+
+// Clears the user state when `parse_with_state` returns or unwinds, so the
+// session never keeps a pointer to it past the borrow.
+struct StateGuard<'g, 's>(&'g ParseSession<'s>);
+
+impl Drop for StateGuard<'_, '_> {
+    fn drop(&mut self) {
+        self.0.clear_state();
+    }
+}
+
+impl<'s> Default for ParseSession<'s> {
+    fn default() -> Self {
+        ParseSession {
+            source: Cell::default(),
+            cst: false,
+            events: RefCell::default(),
+            max_steps: None,
+            deadline: None,
+            steps: Cell::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            depth: Cell::default(),
+            grow_stack: false,
+            indents: RefCell::default(),
+            furthest: RefCell::default(),
+            state: Cell::new(None),
+            state_borrowed: Cell::new(false),
+        }
+    }
+}
+
+impl<'s> ParseSession<'s> {
+    pub fn new() -> Self {
+        ParseSession::default()
+    }
+
+    /// Records every consumed token so that `cst::parse_cst` can build a
+    /// lossless syntax tree.
+    pub fn with_cst(mut self) -> Self {
+        self.cst = true;
+        self
+    }
+
+    /// Aborts the parse with a `ResourceExhausted` error once parsers have
+    /// been invoked `max_steps` times.
+    pub fn max_steps(mut self, max_steps: usize) -> Self {
+        self.max_steps = Some(max_steps);
+        self
+    }
+
+    /// Aborts the parse with a `ResourceExhausted` error once `deadline`
+    /// has passed.
+    pub fn deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// Fails the parse with a `TooDeep` error once recursive (forward)
+    /// parsers are nested more than `max_depth` deep.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Runs nested parses on heap-allocated stack segments when the current
+    /// stack runs low, so `max_depth` can be raised past what the thread's
+    /// own stack would allow.
+    pub fn grow_stack(mut self) -> Self {
+        self.grow_stack = true;
+        self
+    }
+
+    pub fn parse<P>(&self, parser: &P, input: &'s str) -> ParseResult<'s, P::Output>
+    where
+        P: Parser<'s> + ?Sized,
+    {
+        self.clear_state();
+        self.run(parser, input)
+    }
+
+    fn run<P>(&self, parser: &P, input: &'s str) -> ParseResult<'s, P::Output>
+    where
+        P: Parser<'s> + ?Sized,
+    {
+        self.source.set(input);
+        self.events.borrow_mut().clear();
+        self.steps.set(0);
+        self.depth.set(0);
+        self.indents.borrow_mut().clear();
+        self.furthest.take();
+        match parser.parse_in(input, self) {
+            Err(error) if !error.is_fatal() => Err(self.furthest_error(error)),
+            result => result,
+        }
+    }
+
+    /// Like `parse`, but gives the parsers access to `state` through
+    /// `ParseSession::state` and the `state` combinators.
+    pub fn parse_with_state<P, S: 'static>(
+        &self,
+        parser: &P,
+        input: &'s str,
+        state: &mut S,
+    ) -> ParseResult<'s, P::Output>
+    where
+        P: Parser<'s> + ?Sized,
+    {
+        let state: &mut dyn Any = state;
+        self.state.set(Some(NonNull::from(state)));
+        let _guard = StateGuard(self);
+        self.run(parser, input)
+    }
+
+    fn clear_state(&self) {
+        self.state.set(None);
+        self.state_borrowed.set(false);
+    }
+
+    /// Runs `f` on the user state of the current parse, if there is one of
+    /// type `S`.
+    ///
+    /// Updates are not undone when a combinator backtracks past the parser
+    /// that made them.
+    pub fn state<S: 'static, R>(&self, f: impl FnOnce(&mut S) -> R) -> Option<R> {
+        let mut state = self.state.get()?;
+        assert!(
+            !self.state_borrowed.replace(true),
+            "Parse state is already borrowed"
+        );
+        // SAFETY: the pointer comes from the `&mut S` given to
+        // `parse_with_state`, which is borrowed until the parse is over and
+        // the pointer cleared, even by a panic. The flag rules out
+        // overlapping borrows.
+        let result = unsafe { state.as_mut() }.downcast_mut::<S>().map(f);
+        self.state_borrowed.set(false);
+        result
+    }
+
+    /// Keeps `error`, discarded by a combinator that backtracked past it, if
+    /// it got further into the input than any error kept so far.
+    pub fn discard(&self, error: ParseError) {
+        let mut furthest = self.furthest.borrow_mut();
+        *furthest = Some(match furthest.take() {
+            Some(best) => best.furthest(error),
+            None => error,
+        });
+    }
+
+    // A failed parse is reported where it got furthest, which is often in
+    // an alternative that was backtracked over rather than where the
+    // parse finally gave up.
+    fn furthest_error(&self, error: ParseError) -> ParseError {
+        match self.furthest.take() {
+            Some(furthest) if furthest.remaining < error.remaining => furthest,
+            _ => error,
+        }
+    }
+
+    /// Number of steps taken by the current (or last) parse.
+    pub fn steps(&self) -> usize {
+        self.steps.get()
+    }
+
+    /// Counts one parser invocation against the session's limits.
+    pub fn step(&self, input: &str) -> Result<(), ParseError> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if let Some(max_steps) = self.max_steps {
+            if steps > max_steps {
+                return Err(ParseError::resource_exhausted(
+                    format!("Step budget of {} exhausted", max_steps),
+                    input,
+                ));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if steps.is_multiple_of(DEADLINE_CHECK_INTERVAL) && Instant::now() >= deadline {
+                return Err(ParseError::resource_exhausted(
+                    "Parse deadline exceeded",
+                    input,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `parse` one nesting level deeper, failing if that exceeds the
+    /// session's maximum depth or would leave too little stack.
+    pub fn nested<'a, T>(
+        &self,
+        input: &'a str,
+        parse: impl FnOnce() -> ParseResult<'a, T>,
+    ) -> ParseResult<'a, T> {
+        let depth = self.depth.get();
+        if depth >= self.max_depth {
+            return Err(ParseError::too_deep(
+                format!("Maximum nesting depth of {} exceeded", self.max_depth),
+                input,
+            ));
+        }
+        let result = if self.grow_stack {
+            self.depth.set(depth + 1);
+            stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT, parse)
+        } else if stacker::remaining_stack().is_some_and(|left| left < STACK_RED_ZONE) {
+            return Err(ParseError::too_deep(
+                format!("Nesting depth of {} exhausted the stack", depth),
+                input,
+            ));
+        } else {
+            self.depth.set(depth + 1);
+            parse()
+        };
+        self.depth.set(depth);
+        result
+    }
+
+    /// The input the session was started with.
+    pub fn source(&self) -> &'s str {
+        self.source.get()
+    }
+
+    /// Byte offset of `input`, a suffix of the source, within the source.
+    pub fn offset(&self, input: &str) -> usize {
+        self.source.get().len().saturating_sub(input.len())
+    }
+
+    /// Column of `input` within its line, counted in characters from 0.
+    pub fn column(&self, input: &str) -> usize {
+        let offset = self.offset(input);
+        let source = self.source.get();
+        let line_start = source[..offset].rfind('\n').map_or(0, |i| i + 1);
+        source[line_start..offset].chars().count()
+    }
+
+    /// Indentation of the innermost enclosing block, 0 at the top level.
+    pub fn indentation(&self) -> usize {
+        self.indents.borrow().last().copied().unwrap_or(0)
+    }
+
+    pub(crate) fn push_indentation(&self, column: usize) {
+        self.indents.borrow_mut().push(column);
+    }
+
+    pub(crate) fn pop_indentation(&self) {
+        self.indents.borrow_mut().pop();
+    }
+
+    pub fn is_cst(&self) -> bool {
+        self.cst
+    }
+
+    pub(crate) fn checkpoint(&self) -> usize {
+        if self.cst {
+            self.events.borrow().len()
+        } else {
+            0
+        }
+    }
+
+    /// Forgets the events recorded since `checkpoint`, after a parser that
+    /// produced them has been backtracked over.
+    pub(crate) fn rollback(&self, checkpoint: usize) {
+        if self.cst {
+            self.events.borrow_mut().truncate(checkpoint);
+        }
+    }
+
+    pub(crate) fn event(&self, event: Event) {
+        if self.cst {
+            self.events.borrow_mut().push(event);
+        }
+    }
+
+    /// Records the text between `input` and `remaining` as a token.
+    pub(crate) fn token(&self, kind: &'static str, input: &str, remaining: &str) {
+        if self.cst && input.len() > remaining.len() {
+            let start = self.offset(input);
+            let end = self.offset(remaining);
+            self.event(Event::Token { kind, start, end });
+        }
+    }
+
+    pub(crate) fn take_events(&self) -> Vec<Event> {
+        std::mem::take(&mut self.events.borrow_mut())
+    }
+}
@@ -0,0 +1,212 @@
+// Combinators that read and update user state during a parse started with
+// `Parser::parse_with_state`. They fail if the parse has no state of the
+// type they ask for.
+
+use std::any::type_name;
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use super::error::ParseError;
+use super::grammar::{Grammar, Syntax};
+use super::parser::{ParseResult, Parser, RcParser};
+use super::session::ParseSession;
+
+fn no_state<S>(input: &str) -> ParseError {
+    ParseError::new(
+        format!("No parse state of type {}", type_name::<S>()),
+        input,
+    )
+}
+
+struct GetStateParser<S> {
+    state: PhantomData<S>,
+}
+
+impl<'a, S: Clone + 'static> Parser<'a> for GetStateParser<S> {
+    type Output = S;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        match session.state(|state: &mut S| state.clone()) {
+            Some(state) => Ok((state, input)),
+            None => Err(no_state::<S>(input)),
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, _grammar: &mut Grammar) -> Syntax {
+        Syntax::sequence(Vec::new())
+    }
+}
+
+/// A copy of the current state. Consumes no input.
+pub fn get_state<'a, S: Clone + 'static>() -> RcParser<'a, S> {
+    GetStateParser { state: PhantomData }.to_rc()
+}
+
+struct UpdateStateParser<S, F> {
+    f: F,
+    state: PhantomData<S>,
+}
+
+impl<'a, S: 'static, F: Fn(&mut S) + 'a> Parser<'a> for UpdateStateParser<S, F> {
+    type Output = ();
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        match session.state(|state: &mut S| (self.f)(state)) {
+            Some(()) => Ok(((), input)),
+            None => Err(no_state::<S>(input)),
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, _grammar: &mut Grammar) -> Syntax {
+        Syntax::sequence(Vec::new())
+    }
+}
+
+/// Applies `f` to the state. Consumes no input.
+pub fn update_state<'a, S: 'static>(f: impl Fn(&mut S) + 'a) -> RcParser<'a, ()> {
+    UpdateStateParser {
+        f,
+        state: PhantomData,
+    }
+    .to_rc()
+}
+
+struct WithStateParser<'a, S, T, F> {
+    parser: RcParser<'a, T>,
+    f: F,
+    state: PhantomData<S>,
+}
+
+impl<'a, S: 'static, T: 'a, U, F> Parser<'a> for WithStateParser<'a, S, T, F>
+where
+    F: Fn(T, &mut S) -> Result<U, String> + 'a,
+{
+    type Output = U;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        let (value, remaining) = self.parser.parse_in(input, session)?;
+        match session.state(|state: &mut S| (self.f)(value, state)) {
+            Some(Ok(value)) => Ok((value, remaining)),
+            Some(Err(message)) => Err(ParseError::new(message, input)),
+            None => Err(no_state::<S>(input)),
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, grammar: &mut Grammar) -> Syntax {
+        self.parser.describe(grammar)
+    }
+}
+
+/// Runs `parser`, then combines its output with the state. An `Err` from `f`
+/// fails the parse with that message, positioned where `parser` started.
+pub fn with_state<'a, S: 'static, T: 'a, U: 'a>(
+    parser: RcParser<'a, T>,
+    f: impl Fn(T, &mut S) -> Result<U, String> + 'a,
+) -> RcParser<'a, U> {
+    WithStateParser {
+        parser,
+        f,
+        state: PhantomData,
+    }
+    .to_rc()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::parser_combinator::parser::*;
+
+    #[derive(Default)]
+    struct Symbols {
+        ids: HashMap<String, usize>,
+    }
+
+    fn name<'a>() -> RcParser<'a, &'a str> {
+        pattern("[a-z]+").ws()
+    }
+
+    // `let name` declares a name, giving it the next free id.
+    fn declare<'a>() -> RcParser<'a, usize> {
+        with_state(
+            pstring("let ").right(name()),
+            |name, symbols: &mut Symbols| {
+                let id = symbols.ids.len();
+                symbols.ids.insert(name.to_string(), id);
+                Ok(id)
+            },
+        )
+    }
+
+    // A bare name must have been declared.
+    fn reference<'a>() -> RcParser<'a, usize> {
+        with_state(name(), |name, symbols: &mut Symbols| {
+            symbols
+                .ids
+                .get(name)
+                .copied()
+                .ok_or(format!("Undefined name {}", name))
+        })
+    }
+
+    #[test]
+    fn state_is_threaded_through_the_parse() {
+        let statements = declare().or(reference()).many1();
+        let mut symbols = Symbols::default();
+        let result = statements.parse_with_state("let a let b b a", &mut symbols);
+
+        assert_eq!(result, Ok((vec![0, 1, 1, 0], "")));
+        assert_eq!(symbols.ids.len(), 2);
+
+        let error = declare()
+            .many1()
+            .then(reference())
+            .parse_with_state("let a c", &mut Symbols::default())
+            .unwrap_err();
+        assert!(error.message.ends_with("Undefined name c"));
+        assert_eq!(error.remaining, 1);
+    }
+
+    #[test]
+    fn get_and_update_state() {
+        let counter = update_state(|count: &mut u32| *count += 1).right(get_state::<u32>());
+        let parser = pchar('x').right(counter).many();
+        let mut count = 10u32;
+
+        assert_eq!(
+            parser.parse_with_state("xxx", &mut count),
+            Ok((vec![11, 12, 13], ""))
+        );
+        assert_eq!(count, 13);
+    }
+
+    #[test]
+    fn missing_state_is_an_error() {
+        let error = get_state::<u32>().parse("").unwrap_err();
+        assert_eq!(error.message, "No parse state of type u32");
+    }
+
+    #[test]
+    fn state_is_released_when_a_parser_panics() {
+        let session = ParseSession::new();
+        let panics = update_state(|_: &mut Vec<u64>| panic!("boom"));
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut state: Vec<u64> = vec![1, 2, 3];
+            session.parse_with_state(&panics, "", &mut state)
+        }));
+        assert!(result.is_err());
+
+        let error = session.parse(&get_state::<Vec<u64>>(), "").unwrap_err();
+        assert_eq!(error.message, "No parse state of type alloc::vec::Vec<u64>");
+    }
+}
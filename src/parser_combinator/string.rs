@@ -0,0 +1,223 @@
+// String literal parsers.
+//
+// `string_literal` reads a double-quoted string with Rust-like escapes;
+// `raw_string_literal` reads `r"..."` or `r#"..."#` strings, which have no
+// escapes and end at a quote followed by as many `#`s as they started with.
+
+use std::rc::Rc;
+
+use super::error::ParseError;
+use super::grammar::{Grammar, Syntax};
+use super::parser::{ParseResult, Parser, RcParser};
+use super::session::ParseSession;
+
+struct StringLiteralParser;
+
+// Parses the escape sequence after a backslash, returning the character and
+// the input after the sequence.
+fn escape(input: &str) -> Option<(char, &str)> {
+    let mut chars = input.chars();
+    let c = match chars.next()? {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        '\\' => '\\',
+        '"' => '"',
+        '\'' => '\'',
+        'u' => {
+            let digits = chars.as_str().strip_prefix('{')?;
+            let end = digits.find('}')?;
+            if end == 0 || end > 6 {
+                return None;
+            }
+            let code = u32::from_str_radix(&digits[..end], 16).ok()?;
+            return Some((char::from_u32(code)?, &digits[end + 1..]));
+        }
+        _ => return None,
+    };
+    Some((c, chars.as_str()))
+}
+
+impl<'a> Parser<'a> for StringLiteralParser {
+    type Output = String;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        session.step(input)?;
+        let Some(mut remaining) = input.strip_prefix('"') else {
+            return Err(ParseError::new("Expected '\"'", input));
+        };
+        let mut value = String::new();
+        loop {
+            let mut chars = remaining.chars();
+            match chars.next() {
+                None => return Err(ParseError::new("Unterminated string literal", input)),
+                Some('"') => {
+                    let remaining = chars.as_str();
+                    session.token("text", input, remaining);
+                    return Ok((value, remaining));
+                }
+                Some('\\') => match escape(chars.as_str()) {
+                    Some((c, rest)) => {
+                        value.push(c);
+                        remaining = rest;
+                    }
+                    None => {
+                        let sequence: String = remaining.chars().take(2).collect();
+                        return Err(ParseError::invalid(
+                            format!("Invalid escape sequence {}", sequence),
+                            remaining,
+                        ));
+                    }
+                },
+                Some(c) => {
+                    value.push(c);
+                    remaining = chars.as_str();
+                }
+            }
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    fn describe(&self, _grammar: &mut Grammar) -> Syntax {
+        Syntax::Pattern(r#""([^"\\]|\\[ntr0\\"']|\\u\{[0-9a-fA-F]{1,6}\})*""#.to_string())
+    }
+}
+
+/// A double-quoted string. Supports the escapes `\n`, `\t`, `\r`, `\0`,
+/// `\\`, `\"`, `\'` and `\u{...}` with up to six hex digits.
+pub fn string_literal<'a>() -> RcParser<'a, String> {
+    StringLiteralParser.to_rc()
+}
+
+struct RawStringLiteralParser;
+
+impl<'a> Parser<'a> for RawStringLiteralParser {
+    type Output = String;
+    fn parse_in(&self, input: &'a str, session: &ParseSession) -> ParseResult<'a, Self::Output> {
+        session.step(input)?;
+        let Some(rest) = input.strip_prefix('r') else {
+            return Err(ParseError::new("Expected raw string literal", input));
+        };
+        let hashes = rest.len() - rest.trim_start_matches('#').len();
+        let Some(body) = rest[hashes..].strip_prefix('"') else {
+            return Err(ParseError::new("Expected raw string literal", input));
+        };
+        let terminator = format!("\"{}", "#".repeat(hashes));
+        match body.find(&terminator) {
+            Some(end) => {
+                let remaining = &body[end + terminator.len()..];
+                session.token("text", input, remaining);
+                Ok((body[..end].to_string(), remaining))
+            }
+            None => Err(ParseError::new("Unterminated raw string literal", input)),
+        }
+    }
+
+    fn to_rc(self) -> RcParser<'a, Self::Output> {
+        Rc::new(self)
+    }
+
+    // Only strings with no `#` or a single one are described: a pattern
+    // cannot count the `#`s to match them at the end.
+    fn describe(&self, _grammar: &mut Grammar) -> Syntax {
+        Syntax::Pattern(r##"r("[^"]*"|#"([^"]|"+[^"#])*"+#)"##.to_string())
+    }
+}
+
+/// A raw string such as `r"C:\path"` or `r#"say "hi""#`.
+pub fn raw_string_literal<'a>() -> RcParser<'a, String> {
+    RawStringLiteralParser.to_rc()
+}
+
+/// Quotes `value` so that `string_literal` parses it back unchanged.
+pub fn escape_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\\' => quoted.push_str("\\\\"),
+            '"' => quoted.push_str("\\\""),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser_combinator::parser::*;
+    use crate::parser_combinator::regex::Regex;
+    use crate::parser_combinator::ErrorKind;
+
+    #[test]
+    fn string_literal_escapes() {
+        let source = r#""say \"hi\"\n\tto \u{1F600} \\ ü" rest"#;
+        let expected = "say \"hi\"\n\tto \u{1F600} \\ ü".to_string();
+
+        assert_eq!(string_literal().parse(source), Ok((expected, " rest")));
+        assert_eq!(string_literal().parse("\"\""), Ok((String::new(), "")));
+    }
+
+    #[test]
+    fn invalid_escape_is_a_positioned_error() {
+        let source = "x = \"ab\\qc\"";
+        let assign = pstring("x = ").right(string_literal());
+
+        let error = assign.parse(source).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::Invalid);
+        assert!(error.message.ends_with("Invalid escape sequence \\q"));
+        assert_eq!(error.offset(source), 7);
+
+        let error = string_literal().parse("\"\\u{110000}\"").unwrap_err();
+        assert_eq!(error.message, "Invalid escape sequence \\u");
+        let error = string_literal().parse("\"abc").unwrap_err();
+        assert_eq!(error.message, "Unterminated string literal");
+    }
+
+    #[test]
+    fn raw_string_literals() {
+        let raw = raw_string_literal();
+        assert_eq!(raw.parse(r#"r"C:\dir""#), Ok((r"C:\dir".to_string(), "")));
+        assert_eq!(
+            raw.parse(r###"r#"say "hi""# x"###),
+            Ok((r#"say "hi""#.to_string(), " x"))
+        );
+        assert!(raw.parse(r##"r#"open""##).is_err());
+    }
+
+    #[test]
+    fn descriptions_match_the_parsers() {
+        let described = |syntax: Syntax| match syntax {
+            Syntax::Pattern(pattern) => Regex::new(&pattern).unwrap(),
+            syntax => panic!("expected a pattern, got {syntax:?}"),
+        };
+        let quoted = described(string_literal().describe(&mut Grammar::default()));
+        for source in [r#""a\n\"\u{1F600}""#, r#""""#] {
+            assert!(string_literal().parse(source).is_ok());
+            assert_eq!(quoted.match_len(source), Some(source.len()), "{source}");
+        }
+
+        let raw = described(raw_string_literal().describe(&mut Grammar::default()));
+        for source in [r#"r"C:\dir""#, r##"r#"say "hi""#"##, r##"r#""#"##] {
+            assert!(raw_string_literal().parse(source).is_ok());
+            assert_eq!(raw.match_len(source), Some(source.len()), "{source}");
+        }
+    }
+
+    #[test]
+    fn escape_string_round_trips() {
+        let value = "tab\tquote\"slash\\bell\u{7}é";
+        let quoted = escape_string(value);
+
+        assert_eq!(string_literal().parse(&quoted), Ok((value.to_string(), "")));
+    }
+}
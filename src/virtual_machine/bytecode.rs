@@ -0,0 +1,358 @@
+// A binary encoding of instruction lists.
+//
+// The format is the magic number `PCB1`, a little-endian `u32` instruction
+// count, then each instruction as an opcode byte followed by its operands.
+// Strings are a `u32` byte length and UTF-8 text, jump targets are `u64`s,
+// and values are a type byte followed by their little-endian payload. An
+// array value is a `u32` length followed by its elements, and a map value
+// a `u32` length followed by its keys and values in turn. Functions, inside
+// closures, are their parameter names and then their instructions, each
+// list prefixed with its `u32` length.
+
+use std::rc::Rc;
+
+use crate::parser_combinator::binary::*;
+use crate::parser_combinator::ParseError;
+
+use super::map::Key;
+use super::vm::{Closure, Function, Instruction, Values};
+
+const MAGIC: &[u8] = b"PCB1";
+
+// Decoding recurses into arrays, maps and closures, so deeper nesting in
+// untrusted input is an error rather than a stack overflow.
+const MAX_NESTING: usize = 64;
+
+fn opcode(instruction: &Instruction) -> u8 {
+    match instruction {
+        Instruction::Nop => 0,
+        Instruction::StoreLocal(_) => 1,
+        Instruction::LoadLocal(_) => 2,
+        Instruction::Push(_) => 3,
+        Instruction::Add => 4,
+        Instruction::Sub => 5,
+        Instruction::Mul => 6,
+        Instruction::Div => 7,
+        Instruction::Mod => 8,
+        Instruction::Equal => 9,
+        Instruction::NotEqual => 10,
+        Instruction::Gt => 11,
+        Instruction::Gte => 12,
+        Instruction::Lt => 13,
+        Instruction::Lte => 14,
+        Instruction::And => 15,
+        Instruction::Or => 16,
+        Instruction::Ret => 17,
+        Instruction::JumpEqual(_) => 18,
+        Instruction::JumpNotEqual(_) => 19,
+        Instruction::JumpUnconditional(_) => 20,
+        Instruction::Call(_) => 21,
+        Instruction::Neg => 22,
+        Instruction::Not => 23,
+        Instruction::MakeArray(_) => 24,
+        Instruction::Index => 25,
+        Instruction::SetIndex => 26,
+        Instruction::MakeMap(_) => 27,
+        Instruction::Pop => 28,
+        Instruction::MakeClosure(..) => 29,
+        Instruction::CallIndirect(_) => 30,
+    }
+}
+
+fn encode_string(bytes: &mut Vec<u8>, string: &str) {
+    bytes.extend((string.len() as u32).to_le_bytes());
+    bytes.extend(string.as_bytes());
+}
+
+fn encode_strings(bytes: &mut Vec<u8>, strings: &[String]) {
+    bytes.extend((strings.len() as u32).to_le_bytes());
+    for string in strings {
+        encode_string(bytes, string);
+    }
+}
+
+fn encode_function(bytes: &mut Vec<u8>, function: &Function) {
+    encode_strings(bytes, &function.parameters);
+    encode_instructions(bytes, &function.instructions);
+}
+
+fn encode_value(bytes: &mut Vec<u8>, value: &Values) {
+    match value {
+        Values::Int(i) => {
+            bytes.push(0);
+            bytes.extend(i.to_le_bytes());
+        }
+        Values::Float(f) => {
+            bytes.push(1);
+            bytes.extend(f.to_le_bytes());
+        }
+        Values::Bool(b) => bytes.extend([2, *b as u8]),
+        Values::String(s) => {
+            bytes.push(3);
+            encode_string(bytes, s);
+        }
+        Values::Null => bytes.push(4),
+        Values::Array(items) => {
+            let items = items.borrow();
+            bytes.push(5);
+            bytes.extend((items.len() as u32).to_le_bytes());
+            for item in items.iter() {
+                encode_value(bytes, item);
+            }
+        }
+        Values::Map(map) => {
+            let map = map.borrow();
+            bytes.push(6);
+            bytes.extend((map.len() as u32).to_le_bytes());
+            for (key, value) in map.iter() {
+                encode_value(bytes, &key.clone().into());
+                encode_value(bytes, value);
+            }
+        }
+        Values::Function(closure) => {
+            bytes.push(7);
+            encode_function(bytes, &closure.function);
+            // Sorted, so that equal closures encode the same way.
+            let mut captures: Vec<_> = closure.captures.iter().collect();
+            captures.sort_by_key(|(name, _)| *name);
+            bytes.extend((captures.len() as u32).to_le_bytes());
+            for (name, value) in captures {
+                encode_string(bytes, name);
+                encode_value(bytes, value);
+            }
+        }
+    }
+}
+
+fn encode_instructions(bytes: &mut Vec<u8>, instructions: &[Instruction]) {
+    bytes.extend((instructions.len() as u32).to_le_bytes());
+    for instruction in instructions {
+        bytes.push(opcode(instruction));
+        match instruction {
+            Instruction::StoreLocal(name)
+            | Instruction::LoadLocal(name)
+            | Instruction::Call(name) => encode_string(bytes, name),
+            Instruction::JumpEqual(target)
+            | Instruction::JumpNotEqual(target)
+            | Instruction::JumpUnconditional(target) => {
+                bytes.extend((*target as u64).to_le_bytes())
+            }
+            Instruction::Push(value) => encode_value(bytes, value),
+            Instruction::MakeArray(count)
+            | Instruction::MakeMap(count)
+            | Instruction::CallIndirect(count) => bytes.extend((*count as u32).to_le_bytes()),
+            Instruction::MakeClosure(function, names) => {
+                encode_function(bytes, function);
+                encode_strings(bytes, names);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Serializes `instructions` so that `decode` reads them back unchanged.
+pub fn encode(instructions: &[Instruction]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    encode_instructions(&mut bytes, instructions);
+    bytes
+}
+
+fn string<'a>() -> RcByteParser<'a, String> {
+    le_u32()
+        .flat_map(|len| take(len as usize))
+        .try_map(|bytes| {
+            String::from_utf8(bytes.to_vec()).map_err(|_| "Invalid UTF-8 in string".to_string())
+        })
+}
+
+fn strings<'a>() -> RcByteParser<'a, Vec<String>> {
+    le_u32().flat_map(|len| string().count(len as usize))
+}
+
+fn function<'a>(depth: usize) -> RcByteParser<'a, Rc<Function>> {
+    strings()
+        .then(instructions(depth + 1))
+        .map(|(parameters, instructions)| Rc::new(Function::new(parameters, instructions)))
+}
+
+fn target<'a>() -> RcByteParser<'a, usize> {
+    le_u64().try_map(|target| {
+        usize::try_from(target).map_err(|_| format!("Jump target {} is out of range", target))
+    })
+}
+
+// `depth` counts the arrays, maps and functions the value is inside.
+fn value<'a>(depth: usize) -> RcByteParser<'a, Values> {
+    if depth > MAX_NESTING {
+        return fail(format!("Values are nested more than {} deep", MAX_NESTING));
+    }
+    u8().flat_map(move |tag| match tag {
+        0 => le_i32().map(Values::Int),
+        1 => le_f64().map(Values::Float),
+        2 => u8().map(|b| Values::Bool(b != 0)),
+        3 => string().map(Values::String),
+        4 => take(0).map(|_| Values::Null),
+        5 => le_u32()
+            .flat_map(move |len| value(depth + 1).count(len as usize))
+            .map(Values::array),
+        6 => le_u32()
+            .flat_map(move |len| key(depth + 1).then(value(depth + 1)).count(len as usize))
+            .map(|entries| Values::map(entries.into_iter().collect())),
+        7 => function(depth)
+            .then(le_u32().flat_map(move |len| {
+                string().then(value(depth + 1)).count(len as usize)
+            }))
+            .map(|(function, captures)| {
+                let captures = captures.into_iter().collect();
+                Values::Function(Rc::new(Closure { function, captures }))
+            }),
+        _ => fail(format!("Unknown value type {}", tag)),
+    })
+}
+
+fn key<'a>(depth: usize) -> RcByteParser<'a, Key> {
+    value(depth)
+        .try_map(|value| Key::try_from(value).map_err(|value| format!("Invalid map key {}", value)))
+}
+
+fn fail<'a, T: 'a>(message: String) -> RcByteParser<'a, T> {
+    take(0).try_map(move |_| Err(message.clone()))
+}
+
+fn simple<'a>(instruction: Instruction) -> RcByteParser<'a, Instruction> {
+    take(0).map(move |_| instruction.clone())
+}
+
+fn instruction<'a>(depth: usize) -> RcByteParser<'a, Instruction> {
+    u8().flat_map(move |opcode| match opcode {
+        0 => simple(Instruction::Nop),
+        1 => string().map(Instruction::StoreLocal),
+        2 => string().map(Instruction::LoadLocal),
+        3 => value(depth).map(Instruction::Push),
+        4 => simple(Instruction::Add),
+        5 => simple(Instruction::Sub),
+        6 => simple(Instruction::Mul),
+        7 => simple(Instruction::Div),
+        8 => simple(Instruction::Mod),
+        9 => simple(Instruction::Equal),
+        10 => simple(Instruction::NotEqual),
+        11 => simple(Instruction::Gt),
+        12 => simple(Instruction::Gte),
+        13 => simple(Instruction::Lt),
+        14 => simple(Instruction::Lte),
+        15 => simple(Instruction::And),
+        16 => simple(Instruction::Or),
+        17 => simple(Instruction::Ret),
+        18 => target().map(Instruction::JumpEqual),
+        19 => target().map(Instruction::JumpNotEqual),
+        20 => target().map(Instruction::JumpUnconditional),
+        21 => string().map(Instruction::Call),
+        22 => simple(Instruction::Neg),
+        23 => simple(Instruction::Not),
+        24 => le_u32().map(|count| Instruction::MakeArray(count as usize)),
+        25 => simple(Instruction::Index),
+        26 => simple(Instruction::SetIndex),
+        27 => le_u32().map(|count| Instruction::MakeMap(count as usize)),
+        28 => simple(Instruction::Pop),
+        29 => function(depth)
+            .then(strings())
+            .map(|(function, names)| Instruction::MakeClosure(function, names)),
+        30 => le_u32().map(|count| Instruction::CallIndirect(count as usize)),
+        _ => fail(format!("Unknown opcode {}", opcode)),
+    })
+}
+
+fn instructions<'a>(depth: usize) -> RcByteParser<'a, Vec<Instruction>> {
+    le_u32().flat_map(move |n| instruction(depth).count(n as usize))
+}
+
+/// Reads instructions written by `encode`. Errors count the bytes left
+/// where decoding failed.
+pub fn decode(bytes: &[u8]) -> Result<Vec<Instruction>, ParseError> {
+    let instructions = tag(MAGIC).right(instructions(0));
+    match instructions.parse(bytes)? {
+        (instructions, []) => Ok(instructions),
+        (_, rest) => Err(ParseError {
+            remaining: rest.len(),
+            ..ParseError::new("Unexpected bytes after the last instruction", "")
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::virtual_machine::Map;
+
+    #[test]
+    fn instructions_round_trip() {
+        let function = Rc::new(Function::new(
+            vec!["x".to_string()],
+            vec![Instruction::LoadLocal("x".to_string()), Instruction::Ret],
+        ));
+        let instructions = vec![
+            Instruction::Push(Values::Int(-3)),
+            Instruction::Push(Values::Float(0.5)),
+            Instruction::Push(Values::Bool(true)),
+            Instruction::Push(Values::String("héllo".to_string())),
+            Instruction::Push(Values::Null),
+            Instruction::Push(Values::array(vec![Values::Int(1), Values::array(vec![])])),
+            Instruction::Push(Values::map(Map::from_iter([
+                (Key::String("k".to_string()), Values::Int(1)),
+                (Key::Bool(false), Values::Null),
+            ]))),
+            Instruction::MakeArray(2),
+            Instruction::MakeMap(1),
+            Instruction::Pop,
+            Instruction::Index,
+            Instruction::SetIndex,
+            Instruction::StoreLocal("x".to_string()),
+            Instruction::LoadLocal("x".to_string()),
+            Instruction::Lte,
+            Instruction::Neg,
+            Instruction::Not,
+            Instruction::JumpNotEqual(12),
+            Instruction::Call("main".to_string()),
+            Instruction::CallIndirect(2),
+            Instruction::MakeClosure(function.clone(), vec!["n".to_string()]),
+            Instruction::Push(Values::Function(Rc::new(Closure {
+                function,
+                captures: [("n".to_string(), Values::Int(1))].into_iter().collect(),
+            }))),
+            Instruction::Ret,
+        ];
+        let bytes = encode(&instructions);
+
+        assert_eq!(&bytes[..4], b"PCB1");
+        assert_eq!(decode(&bytes), Ok(instructions));
+    }
+
+    #[test]
+    fn unknown_opcode_is_an_error() {
+        let mut bytes = encode(&[Instruction::Nop, Instruction::Add]);
+        bytes[9] = 99;
+
+        let error = decode(&bytes).unwrap_err();
+        assert_eq!(error.message, "Unknown opcode 99");
+        assert_eq!(error.remaining, 0);
+
+        let error = decode(b"PCB2").unwrap_err();
+        assert_eq!(error.message, "Expected \"PCB1\"");
+    }
+
+    #[test]
+    fn deep_nesting_is_an_error_not_a_stack_overflow() {
+        let nested = |depth: usize| {
+            let mut bytes = b"PCB1\x01\x00\x00\x00\x03".to_vec();
+            for _ in 0..depth {
+                bytes.extend([5, 1, 0, 0, 0]);
+            }
+            bytes.push(4);
+            bytes
+        };
+
+        assert!(decode(&nested(MAX_NESTING)).is_ok());
+        let error = decode(&nested(200_000)).unwrap_err();
+        assert_eq!(error.message, "Values are nested more than 64 deep");
+    }
+}
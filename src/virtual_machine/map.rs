@@ -0,0 +1,160 @@
+// The map behind `Values::Map`.
+//
+// Keys are strings, ints or bools. Entries iterate in the order their keys
+// were first inserted: assigning to an existing key keeps its place, while a
+// key that is removed and inserted again moves to the end.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use super::vm::Values;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    Int(i32),
+    Bool(bool),
+    String(String),
+}
+
+impl TryFrom<Values> for Key {
+    type Error = Values;
+
+    /// Fails with the value itself if it cannot be a key.
+    fn try_from(value: Values) -> Result<Self, Self::Error> {
+        match value {
+            Values::Int(i) => Ok(Key::Int(i)),
+            Values::Bool(b) => Ok(Key::Bool(b)),
+            Values::String(s) => Ok(Key::String(s)),
+            value => Err(value),
+        }
+    }
+}
+
+impl From<Key> for Values {
+    fn from(key: Key) -> Self {
+        match key {
+            Key::Int(i) => Values::Int(i),
+            Key::Bool(b) => Values::Bool(b),
+            Key::String(s) => Values::String(s),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Key::Int(i) => write!(f, "{}", i),
+            Key::Bool(b) => write!(f, "{}", b),
+            Key::String(s) => write!(f, "{:?}", s),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Map {
+    entries: Vec<(Key, Values)>,
+    positions: HashMap<Key, usize>,
+}
+
+impl Map {
+    pub fn new() -> Self {
+        Map::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&Values> {
+        self.positions.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains_key(&self, key: &Key) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    /// Sets the value for `key`, returning the value it replaced.
+    pub fn insert(&mut self, key: Key, value: Values) -> Option<Values> {
+        match self.positions.get(&key) {
+            Some(&i) => Some(std::mem::replace(&mut self.entries[i].1, value)),
+            None => {
+                self.positions.insert(key.clone(), self.entries.len());
+                self.entries.push((key, value));
+                None
+            }
+        }
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<Values> {
+        let i = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self.positions.get_mut(key).unwrap() -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &Values)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Values> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+/// Maps are equal if they have the same entries, in any order.
+impl PartialEq for Map {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(key, value)| other.get(key) == Some(value))
+    }
+}
+
+impl FromIterator<(Key, Values)> for Map {
+    fn from_iter<I: IntoIterator<Item = (Key, Values)>>(entries: I) -> Self {
+        let mut map = Map::new();
+        for (key, value) in entries {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(s: &str) -> Key {
+        Key::String(s.to_string())
+    }
+
+    #[test]
+    fn entries_keep_insertion_order() {
+        let mut map: Map = [(key("b"), Values::Int(1)), (Key::Int(2), Values::Null)]
+            .into_iter()
+            .collect();
+        map.insert(Key::Bool(true), Values::Int(3));
+        assert_eq!(map.insert(key("b"), Values::Int(4)), Some(Values::Int(1)));
+        let keys: Vec<_> = map.keys().map(|key| key.to_string()).collect();
+        assert_eq!(keys, ["\"b\"", "2", "true"]);
+
+        assert_eq!(map.remove(&Key::Int(2)), Some(Values::Null));
+        map.insert(Key::Int(2), Values::Int(5));
+        assert_eq!(map.get(&Key::Bool(true)), Some(&Values::Int(3)));
+        let values: Vec<_> = map.values().cloned().collect();
+        assert_eq!(values, [Values::Int(4), Values::Int(3), Values::Int(5)]);
+
+        assert_eq!(Key::try_from(Values::Float(1.0)), Err(Values::Float(1.0)));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod bytecode;
+pub mod map;
 pub mod vm;
 
+pub use map::{Key, Map};
 pub use vm::*;
@@ -1,20 +1,30 @@
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+use super::map::{Key, Map};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Values {
     Int(i32),
-    Float(f32),
+    Float(f64),
     Bool(bool),
     String(String),
-    /*Array,
-    Map,
-    Function,
     Null,
-    Undefined,*/
+    /// Copies of an array value share its elements, so a `push` through one
+    /// is seen through all of them.
+    Array(Rc<RefCell<Vec<Values>>>),
+    /// Shared like `Array`.
+    Map(Rc<RefCell<Map>>),
+    Function(Rc<Closure>),
+    /*Undefined,*/
 }
 
 const EMPTY_STACK : &str = "Stack Empty - please check IL";
 const ADDITION_NOT_SUPPORTED : &str = "Addition not supported for Values";
+const UNARY_NOT_SUPPORTED : &str = "Unary operator not supported for Value";
+const INDEX_NOT_SUPPORTED : &str = "Indexing not supported for Values";
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Types {
@@ -22,11 +32,11 @@ pub enum Types {
     Float,
     Bool,
     String,
-    /*Array,
+    Null,
+    Array,
     Map,
     Function,
-    Null,
-    Undefined,*/
+    /*Undefined,*/
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -35,12 +45,14 @@ pub enum Instruction {
     StoreLocal(String),
     LoadLocal(String),
     Push(Values),
+    Pop,
     //Math
     Add,
     Sub,
     Mul,
     Div,
     Mod,
+    Neg,
     //Comparison
     Equal,
     NotEqual,
@@ -51,12 +63,25 @@ pub enum Instruction {
     //Logical Operators
     And,
     Or,
+    Not,
     Ret,
     //Control Flow
     JumpEqual(usize),
     JumpNotEqual(usize),
     JumpUnconditional(usize),
     Call(String),
+    //Arrays
+    MakeArray(usize),
+    Index,
+    SetIndex,
+    //Maps
+    MakeMap(usize),
+    //Functions
+    /// Makes a closure over `Function` that copies the named locals, skipping
+    /// any that are not set.
+    MakeClosure(Rc<Function>, Vec<String>),
+    /// Calls the function value below its `usize` arguments on the stack.
+    CallIndirect(usize),
 }
 
 struct StackFrame {
@@ -75,7 +100,7 @@ impl StackFrame {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Function {
     pub parameters: Vec<String>,
     pub instructions: Vec<Instruction>,
@@ -90,6 +115,14 @@ impl Function {
     }
 }
 
+/// A function value: a function and the values of the enclosing locals it
+/// uses, as they were when the closure was made.
+#[derive(Debug, PartialEq)]
+pub struct Closure {
+    pub function: Rc<Function>,
+    pub captures: HashMap<String, Values>,
+}
+
 #[derive(Debug, Default)]
 pub struct Module {
     pub functions : HashMap<String, Function>
@@ -101,19 +134,35 @@ impl Module {
     }
 }
 
-fn add(left: Values, right: Values) -> Values {
+// Arithmetic and comparison on an Int and a Float widen the Int to Float.
+fn promote(left: Values, right: Values) -> (Values, Values) {
     match (left, right) {
+        (Values::Int(left), Values::Float(right)) => (Values::Float(left as f64), Values::Float(right)),
+        (Values::Float(left), Values::Int(right)) => (Values::Float(left), Values::Float(right as f64)),
+        (left, right) => (left, right),
+    }
+}
+
+fn equal(left: Values, right: Values) -> bool {
+    let (left, right) = promote(left, right);
+    left == right
+}
+
+fn add(left: Values, right: Values) -> Values {
+    match promote(left, right) {
         (Values::Int(left), Values::Int(right)) => Values::Int(left + right),
         (Values::Float(left), Values::Float(right)) => Values::Float(left + right),
         (Values::String(left), Values::String(right)) => Values::String(left + &right),
         (Values::Int(left), Values::String(right)) => Values::String(left.to_string() + &right),
         (Values::String(left), Values::Int(right)) => Values::String(left + &right.to_string()),
+        (Values::Float(left), Values::String(right)) => Values::String(left.to_string() + &right),
+        (Values::String(left), Values::Float(right)) => Values::String(left + &right.to_string()),
         _ => panic!("{ADDITION_NOT_SUPPORTED}"),
     }
 }
 
 fn subtract(left: Values, right: Values) -> Values {
-    match (left, right) {
+    match promote(left, right) {
         (Values::Int(left), Values::Int(right)) => Values::Int(left - right),
         (Values::Float(left), Values::Float(right)) => Values::Float(left - right),
         _ => panic!("{ADDITION_NOT_SUPPORTED}"),
@@ -121,7 +170,7 @@ fn subtract(left: Values, right: Values) -> Values {
 }
 
 fn mul(left: Values, right: Values) -> Values {
-    match (left, right) {
+    match promote(left, right) {
         (Values::Int(left), Values::Int(right)) => Values::Int(left * right),
         (Values::Float(left), Values::Float(right)) => Values::Float(left * right),
         _ => panic!("{ADDITION_NOT_SUPPORTED}"),
@@ -129,7 +178,7 @@ fn mul(left: Values, right: Values) -> Values {
 }
 
 fn div(left: Values, right: Values) -> Values {
-    match (left, right) {
+    match promote(left, right) {
         (Values::Int(left), Values::Int(right)) => Values::Int(left / right),
         (Values::Float(left), Values::Float(right)) => Values::Float(left / right),
         _ => panic!("{ADDITION_NOT_SUPPORTED}"),
@@ -137,14 +186,15 @@ fn div(left: Values, right: Values) -> Values {
 }
 
 fn modulus(left: Values, right: Values) -> Values {
-    match (left, right) {
+    match promote(left, right) {
         (Values::Int(left), Values::Int(right)) => Values::Int(left % right),
+        (Values::Float(left), Values::Float(right)) => Values::Float(left % right),
         _ => panic!("{ADDITION_NOT_SUPPORTED}"),
     }
 }
 
 fn lt(left: Values, right: Values) -> Values {
-    match (left, right) {
+    match promote(left, right) {
         (Values::Int(left), Values::Int(right)) => Values::Bool(left < right),
         (Values::Float(left), Values::Float(right)) => Values::Bool(left < right),
         _ => panic!("{ADDITION_NOT_SUPPORTED}"),
@@ -152,7 +202,7 @@ fn lt(left: Values, right: Values) -> Values {
 }
 
 fn lte(left: Values, right: Values) -> Values {
-    match (left, right) {
+    match promote(left, right) {
         (Values::Int(left), Values::Int(right)) => Values::Bool(left <= right),
         (Values::Float(left), Values::Float(right)) => Values::Bool(left <= right),
         _ => panic!("{ADDITION_NOT_SUPPORTED}"),
@@ -160,7 +210,7 @@ fn lte(left: Values, right: Values) -> Values {
 }
 
 fn gt(left: Values, right: Values) -> Values {
-    match (left, right) {
+    match promote(left, right) {
         (Values::Int(left), Values::Int(right)) => Values::Bool(left > right),
         (Values::Float(left), Values::Float(right)) => Values::Bool(left > right),
         _ => panic!("{ADDITION_NOT_SUPPORTED}"),
@@ -168,7 +218,7 @@ fn gt(left: Values, right: Values) -> Values {
 }
 
 fn gte(left: Values, right: Values) -> Values {
-    match (left, right) {
+    match promote(left, right) {
         (Values::Int(left), Values::Int(right)) => Values::Bool(left >= right),
         (Values::Float(left), Values::Float(right)) => Values::Bool(left >= right),
         _ => panic!("{ADDITION_NOT_SUPPORTED}"),
@@ -189,13 +239,198 @@ fn or(left: Values, right: Values) -> Values {
     }
 }
 
+fn negate(value: Values) -> Values {
+    match value {
+        Values::Int(value) => match value.checked_neg() {
+            Some(negated) => Values::Int(negated),
+            None => panic!("Negating {} overflows an int", value),
+        },
+        Values::Float(value) => Values::Float(-value),
+        _ => panic!("{UNARY_NOT_SUPPORTED}"),
+    }
+}
+
+fn not(value: Values) -> Values {
+    match value {
+        Values::Bool(value) => Values::Bool(!value),
+        _ => panic!("{UNARY_NOT_SUPPORTED}"),
+    }
+}
+
+impl Values {
+    pub fn array(items: Vec<Values>) -> Values {
+        Values::Array(Rc::new(RefCell::new(items)))
+    }
+
+    pub fn map(map: Map) -> Values {
+        Values::Map(Rc::new(RefCell::new(map)))
+    }
+}
+
+// Strings inside arrays and maps are quoted.
+fn fmt_nested(value: &Values, f: &mut fmt::Formatter) -> fmt::Result {
+    match value {
+        Values::String(s) => write!(f, "{:?}", s),
+        value => write!(f, "{}", value),
+    }
+}
+
+impl fmt::Display for Values {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Values::Int(i) => write!(f, "{}", i),
+            Values::Float(x) => write!(f, "{}", x),
+            Values::Bool(b) => write!(f, "{}", b),
+            Values::String(s) => write!(f, "{}", s),
+            Values::Null => write!(f, "null"),
+            Values::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    fmt_nested(item, f)?;
+                }
+                write!(f, "]")
+            }
+            Values::Map(map) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in map.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: ", key)?;
+                    fmt_nested(value, f)?;
+                }
+                write!(f, "}}")
+            }
+            Values::Function(closure) => {
+                write!(f, "<fn({})>", closure.function.parameters.join(", "))
+            }
+        }
+    }
+}
+
+// The position `index` refers to in `items`, which must be in bounds.
+fn position(items: &[Values], index: Values) -> usize {
+    let Values::Int(index) = index else {
+        panic!("{INDEX_NOT_SUPPORTED}");
+    };
+    match usize::try_from(index) {
+        Ok(position) if position < items.len() => position,
+        _ => panic!("Index {} is out of bounds for an array of length {}", index, items.len()),
+    }
+}
+
+fn key(value: Values) -> Key {
+    Key::try_from(value)
+        .unwrap_or_else(|value| panic!("Map keys must be strings, ints or bools, not {}", value))
+}
+
+fn index(container: Values, index: Values) -> Values {
+    match container {
+        Values::Array(items) => {
+            let items = items.borrow();
+            items[position(&items, index)].clone()
+        }
+        Values::Map(map) => {
+            let key = key(index);
+            map.borrow()
+                .get(&key)
+                .cloned()
+                .unwrap_or_else(|| panic!("Key {} is not in the map", key))
+        }
+        _ => panic!("{INDEX_NOT_SUPPORTED}"),
+    }
+}
+
+fn set_index(container: Values, index: Values, value: Values) {
+    match container {
+        Values::Array(items) => {
+            let mut items = items.borrow_mut();
+            let position = position(&items, index);
+            items[position] = value;
+        }
+        Values::Map(map) => {
+            map.borrow_mut().insert(key(index), value);
+        }
+        _ => panic!("{INDEX_NOT_SUPPORTED}"),
+    }
+}
+
+fn len(value: Values) -> Values {
+    match value {
+        Values::Array(items) => Values::Int(items.borrow().len() as i32),
+        Values::Map(map) => Values::Int(map.borrow().len() as i32),
+        _ => panic!("{UNARY_NOT_SUPPORTED}"),
+    }
+}
+
+fn keys(map: Values) -> Values {
+    match map {
+        Values::Map(map) => Values::array(map.borrow().keys().cloned().map(Values::from).collect()),
+        _ => panic!("{INDEX_NOT_SUPPORTED}"),
+    }
+}
+
+fn values(map: Values) -> Values {
+    match map {
+        Values::Map(map) => Values::array(map.borrow().values().cloned().collect()),
+        _ => panic!("{INDEX_NOT_SUPPORTED}"),
+    }
+}
+
+fn contains(map: Values, index: Values) -> Values {
+    match map {
+        Values::Map(map) => Values::Bool(map.borrow().contains_key(&key(index))),
+        _ => panic!("{INDEX_NOT_SUPPORTED}"),
+    }
+}
+
+// Removes the entry for `index`, returning its value, or null if there was
+// none.
+fn remove(map: Values, index: Values) -> Values {
+    match map {
+        Values::Map(map) => map.borrow_mut().remove(&key(index)).unwrap_or(Values::Null),
+        _ => panic!("{INDEX_NOT_SUPPORTED}"),
+    }
+}
+
+fn push(array: Values, value: Values) {
+    match array {
+        Values::Array(items) => items.borrow_mut().push(value),
+        _ => panic!("{INDEX_NOT_SUPPORTED}"),
+    }
+}
+
+fn pop(array: Values) -> Values {
+    match array {
+        Values::Array(items) => items
+            .borrow_mut()
+            .pop()
+            .unwrap_or_else(|| panic!("Cannot pop from an empty array")),
+        _ => panic!("{INDEX_NOT_SUPPORTED}"),
+    }
+}
+
 pub struct Program {
-    functions: HashMap<String, Function>,
+    functions: HashMap<String, Rc<Function>>,
 }
 
 impl Program {
     pub fn new(module: Module) -> Program {
-        Program { functions : module.functions }
+        let functions = module.functions.into_iter().map(|(name, function)| (name, Rc::new(function)));
+        Program { functions : functions.collect() }
+    }
+
+    // Runs `function` with `arguments` bound to its parameters, on top of
+    // `locals`. Every call produces a value, even if `function` ends without
+    // one.
+    fn call(&self, function: &Function, mut locals: HashMap<String, Values>, arguments: Vec<Values>) -> Values {
+        for (parameter, argument) in function.parameters.iter().zip(arguments) {
+            locals.insert(parameter.clone(), argument);
+        }
+        self.eval(function, locals).unwrap_or(Values::Null)
     }
 
     pub fn main(&self) -> &Function {
@@ -214,6 +449,10 @@ impl Program {
                     stack_frame.stack.push(value.clone());
                     ip += 1;
                 }
+                Instruction::Pop => {
+                    stack_frame.stack.pop().expect(EMPTY_STACK);
+                    ip += 1;
+                }
                 Instruction::StoreLocal(name) => {
                     stack_frame
                         .locals
@@ -221,8 +460,16 @@ impl Program {
                     ip += 1;
                 }
                 Instruction::LoadLocal(name) => {
-                    let variable = stack_frame.locals.get(name).unwrap_or_else(|| panic!("Missing {name}"));
-                    stack_frame.stack.push(variable.clone());
+                    let variable = match (stack_frame.locals.get(name), self.functions.get(name)) {
+                        (Some(variable), _) => variable.clone(),
+                        // A named function used as a value.
+                        (None, Some(function)) => Values::Function(Rc::new(Closure {
+                            function: function.clone(),
+                            captures: HashMap::new(),
+                        })),
+                        (None, None) => panic!("Missing {name}"),
+                    };
+                    stack_frame.stack.push(variable);
                     ip += 1;
                 }
                 Instruction::Add => {
@@ -255,6 +502,16 @@ impl Program {
                     stack_frame.stack.push(modulus(left, right));
                     ip += 1;
                 }
+                Instruction::Neg => {
+                    let value = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    stack_frame.stack.push(negate(value));
+                    ip += 1;
+                }
+                Instruction::Not => {
+                    let value = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    stack_frame.stack.push(not(value));
+                    ip += 1;
+                }
                 Instruction::Ret => {
                     let return_value = stack_frame.stack.pop();
                     stack_frame.return_value = return_value;
@@ -263,13 +520,13 @@ impl Program {
                 Instruction::Equal => {
                     let right = stack_frame.stack.pop().expect(EMPTY_STACK);
                     let left = stack_frame.stack.pop().expect(EMPTY_STACK);
-                    stack_frame.stack.push(Values::Bool(left == right));
+                    stack_frame.stack.push(Values::Bool(equal(left, right)));
                     ip += 1;
                 }
                 Instruction::NotEqual => {
                     let right = stack_frame.stack.pop().expect(EMPTY_STACK);
                     let left = stack_frame.stack.pop().expect(EMPTY_STACK);
-                    stack_frame.stack.push(Values::Bool(left != right));
+                    stack_frame.stack.push(Values::Bool(!equal(left, right)));
                     ip += 1;
                 }
                 Instruction::Gt => {
@@ -298,33 +555,82 @@ impl Program {
                 }
                 Instruction::Call(name) if name == "print" => {
                     let value_to_print = stack_frame.stack.pop().expect(EMPTY_STACK);
-                    match value_to_print {
-                        Values::Bool(b) =>  println!("{}", b),
-                        Values::String(s) =>  println!("{}", s),
-                        Values::Int(i) =>  println!("{}", i),
-                        Values::Float(f) =>  println!("{}", f),
-                    }
+                    println!("{}", value_to_print);
+                    stack_frame.stack.push(Values::Null);
+                    ip += 1;
+                }
+                Instruction::Call(name) if name == "len" => {
+                    let value = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    stack_frame.stack.push(len(value));
+                    ip += 1;
+                }
+                Instruction::Call(name) if name == "push" => {
+                    let value = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    let array = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    push(array, value);
+                    stack_frame.stack.push(Values::Null);
+                    ip += 1;
+                }
+                Instruction::Call(name) if name == "pop" => {
+                    let array = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    stack_frame.stack.push(pop(array));
+                    ip += 1;
+                }
+                Instruction::Call(name) if name == "keys" => {
+                    let map = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    stack_frame.stack.push(keys(map));
+                    ip += 1;
+                }
+                Instruction::Call(name) if name == "values" => {
+                    let map = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    stack_frame.stack.push(values(map));
+                    ip += 1;
+                }
+                Instruction::Call(name) if name == "contains" => {
+                    let key = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    let map = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    stack_frame.stack.push(contains(map, key));
+                    ip += 1;
+                }
+                Instruction::Call(name) if name == "remove" => {
+                    let key = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    let map = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    stack_frame.stack.push(remove(map, key));
                     ip += 1;
                 }
 
                 Instruction::Call(function_name) => {
                     let function = self.functions.get(function_name.as_str()).unwrap();
-                    let mut parameters_values = Vec::new();
-                    for _ in 0.. function.parameters.len() {
-                        let value = stack_frame.stack.pop().expect(EMPTY_STACK);
-                        parameters_values.push(value);
-                    }
-                    parameters_values.reverse();
-                    let mut parameters = HashMap::new();
-                    for (p, pvalue) in parameters_values.iter().enumerate()  {
-                        parameters.insert(function.parameters[p].clone(), pvalue.clone());
-                    }
+                    let start = stack_frame.stack.len().checked_sub(function.parameters.len()).expect(EMPTY_STACK);
+                    let arguments = stack_frame.stack.split_off(start);
 
-                    let return_value = self.eval(function, parameters);
-
-                    if let Some(return_value) = return_value {
-                        stack_frame.stack.push(return_value);
+                    let return_value = self.call(function, HashMap::new(), arguments);
+                    stack_frame.stack.push(return_value);
+                    ip += 1;
+                }
+                Instruction::CallIndirect(count) => {
+                    let start = stack_frame.stack.len().checked_sub(*count).expect(EMPTY_STACK);
+                    let arguments = stack_frame.stack.split_off(start);
+                    let callee = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    let Values::Function(closure) = callee else {
+                        panic!("{} is not a function", callee);
+                    };
+                    let parameters = closure.function.parameters.len();
+                    if parameters != *count {
+                        panic!("Function takes {} arguments but {} were given", parameters, count);
                     }
+
+                    let return_value = self.call(&closure.function, closure.captures.clone(), arguments);
+                    stack_frame.stack.push(return_value);
+                    ip += 1;
+                }
+                Instruction::MakeClosure(function, names) => {
+                    let captures = names
+                        .iter()
+                        .filter_map(|name| Some((name.clone(), stack_frame.locals.get(name)?.clone())))
+                        .collect();
+                    let closure = Closure { function: function.clone(), captures };
+                    stack_frame.stack.push(Values::Function(Rc::new(closure)));
                     ip += 1;
                 }
                 Instruction::And => {
@@ -360,6 +666,35 @@ impl Program {
                 Instruction::JumpUnconditional(location) => {
                     ip = *location;
                 }
+                Instruction::MakeArray(count) => {
+                    let start = stack_frame.stack.len().checked_sub(*count).expect(EMPTY_STACK);
+                    let items = stack_frame.stack.split_off(start);
+                    stack_frame.stack.push(Values::array(items));
+                    ip += 1;
+                }
+                Instruction::MakeMap(count) => {
+                    let start = stack_frame.stack.len().checked_sub(2 * count).expect(EMPTY_STACK);
+                    let mut items = stack_frame.stack.split_off(start).into_iter();
+                    let mut map = Map::new();
+                    while let (Some(k), Some(value)) = (items.next(), items.next()) {
+                        map.insert(key(k), value);
+                    }
+                    stack_frame.stack.push(Values::map(map));
+                    ip += 1;
+                }
+                Instruction::Index => {
+                    let position = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    let array = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    stack_frame.stack.push(index(array, position));
+                    ip += 1;
+                }
+                Instruction::SetIndex => {
+                    let value = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    let position = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    let array = stack_frame.stack.pop().expect(EMPTY_STACK);
+                    set_index(array, position, value);
+                    ip += 1;
+                }
             }
         }
         stack_frame.return_value
@@ -0,0 +1,36 @@
+// Runs the JSON parser over the corpus in `tests/json`. As in JSONTestSuite,
+// files named `y_*` must parse and files named `n_*` must be rejected.
+
+use std::fs;
+use std::path::Path;
+
+use parser_combinator::grammars::json;
+
+#[test]
+fn json_corpus() {
+    let corpus = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/json");
+    let mut failures = Vec::new();
+    let mut count = 0;
+    for entry in fs::read_dir(corpus).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let bytes = fs::read(&path).unwrap();
+        // Input that is not UTF-8 is rejected before it reaches the parser.
+        let accepted = match std::str::from_utf8(&bytes) {
+            Ok(source) => json::parse(source).is_ok(),
+            Err(_) => false,
+        };
+        let expected = match &name[..2] {
+            "y_" => true,
+            "n_" => false,
+            _ => continue,
+        };
+        if accepted != expected {
+            failures.push(name);
+        }
+        count += 1;
+    }
+    failures.sort();
+    assert!(count > 0, "the JSON corpus is empty");
+    assert!(failures.is_empty(), "misclassified: {:?}", failures);
+}